@@ -1,234 +1,751 @@
-use std::env::Args;
+use std::io;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use clap_complete::Shell;
+
+use crate::kdf::DEFAULT_UNLOCK_TIME;
+use crate::kinds::RecordKind;
+use crate::output::{self, Format, ErrorCode};
+use crate::rotation;
+use crate::search::Search;
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Commands {
     Init(String),
     Logs,
-    Add(String),            // Add new entry
-    Get(String),            // Get record based on username or email
-    List,                   // Shows all entries
-    Edit(String),           // Edits the entered record based on username or email
-    Delete(String),          // Deletes a entry
+    Add((String, Labels)),  // Add new entry
+    Get((Query, Option<Pick>)), // Get record based on username or email, or one field of it
+    List(Query),            // Shows all entries, or those with a tag / in a folder
+    Tree(Query),            // Shows the records as a folder tree
+    Tag((TagAction, Query)),    // Adds or removes a tag
+    Move((String, Query)),  // Moves a record into a folder
+    Edit((Query, Labels)),  // Edits the entered record, non-interactively when values are given as flags
+    Delete((Query, bool)),  // Deletes a entry, true skips the confirmation
+    Lookup(String),         // Logins with a url matching the given one
+    Rotation((RotationPolicy, Query)),  // Sets the rotation policy of a record, or of the profile without a name
+    History((Query, Option<usize>)),    // Shows previous passwords, or restores the n-th one
+    Otp(Query),             // Prints the current totp/hotp code
+    OtpSet(Query),          // Stores or removes the otp secret of a record
+    Attach((Query, String)),    // Encrypts a file into the record
+    Attachments(Query),     // Lists the files attached to a record
+    Extract((Query, String, Option<String>)),   // Decrypts an attachment to a file
     Generate(usize),          // Generates a password of 'n' size
     Passwd,                  // change master password
-    Export,                 // Exports to file
+    Export((bool, bool)),   // Exports to file, true for json instead of csv, true to include otp secrets
     Import(String),         // Imports from given path
-    Help,                   // Prints help
-    Invalid(String),        // Invalid command
+    KdfTune(u128),          // Benchmarks argon2id for an unlock time in ms
+    Migrate(bool),          // Upgrades the vault format, true for a dry run
+    Backups(BackupAction),  // Lists, compares or restores automatic backups
+    Completions(Shell),     // Prints a completion script
+    ClearClipboard((String, u64)),  // Started by get --copy, clears the clipboard after n seconds
 
     /* Profile Manipulation */
     Default(String),
     CreateProfile(String),
     EditProfile((String, String)),
     DeleteProfile(String),
-    ListProfiles
+    ListProfiles,
+    SealMetadata,           // Hides profile names and record counts
+    UnsealMetadata,
 }
 
-pub fn parse_args(mut args: Args) -> Option<(Option<String>,Commands)> {
-    let prog_name = args.next().unwrap_or("rsafe".to_string());
-    let (mut profile, mut command) = (None, None);
+/* Which records --get, --edit and --rm work on */
+#[derive(Debug, Default)]
+pub struct Query {
+    pub text: String,           // a search (search.rs) over entry, username, email, note and urls
+    pub search: Search,         // text, parsed
+    pub id: Option<String>,     // --id <uuid>, a unique prefix is enough
+    pub exact: bool,            // --exact, text has to be the whole entry name
+    pub tag: Option<String>,    // --tag <tag>, only records with this tag
+    pub folder: Option<String>, // --folder <path>, only records in this folder or below
+    pub kind: Option<RecordKind>,   // --kind <kind>, only records of this kind
+    pub due: Option<u32>,       // --due <days> / --expired, only logins due for rotation within that many days
+}
 
-    let missing_cmd = |x: &str|
-        println!("[!] Missing argument for '{}'. Try {} --help", x, prog_name);
+/* Kind, tags, folder, custom fields, urls and login values given to add (or edit for fields, urls and values) */
+#[derive(Debug, Default)]
+pub struct Labels {
+    pub kind: RecordKind,
+    pub tags: Vec<String>,
+    pub folder: Option<String>,
+    pub fields: Vec<String>,    // name[:kind]=value
+    pub urls: Vec<String>,      // [rule:]url
+    pub remove_urls: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<PasswordSource>,
+    pub email: Option<String>,  // an empty one clears it on edit
+    pub note: Option<String>,
+    pub yes: bool,              // no prompts, whatever wasn't given is left empty (or unchanged)
+}
 
-    while let Some(cmd) = args.next().as_deref() {
-        match cmd {
-            /* Profile Manipulation */
-            "--set-profile-default" => {
-                if let Some(profile_name) = args.next() {
-                    command = Some(Commands::Default(profile_name));
-                    break;
-                }
-                missing_cmd(cmd);
-            },
+impl Labels {
+    /* Any of --username, --password-stdin, --generate-password, --email or --note */
+    pub fn has_login_values(&self) -> bool {
+        self.username.is_some() || self.password.is_some() || self.email.is_some() || self.note.is_some()
+    }
+}
 
-            "--create-profile" => {
-                if let Some(profile_name) = args.next() {
-                    command = Some(Commands::CreateProfile(profile_name));
-                    break;
-                }
-                missing_cmd(cmd);
-            },
-
-            "--edit-profile" => {
-                if let Some(profile_name) = args.next() {
-                    if let Some(new_profile_name) = args.next() {
-                        command = Some(Commands::EditProfile((profile_name, new_profile_name)));
-                    }
-                    break;
-                }
-                missing_cmd(cmd);
-            },
+#[derive(Debug, Clone, Copy)]
+pub enum PasswordSource {
+    Stdin,              // first line of stdin
+    Generate(usize),    // a random one of that length
+}
 
-            "--delete-profile" => {
-                if let Some(profile_name) = args.next() {
-                    command = Some(Commands::DeleteProfile(profile_name));
-                    break;
-                }
-                missing_cmd(cmd);
-            },
+/* Options every command accepts */
+#[derive(Debug, Default)]
+pub struct Globals {
+    pub profile: Option<String>,        // --from <profile>
+    pub password_fd: Option<i32>,       // --master-password-fd <n>
+}
 
-            "--list-profiles" => {
-                command = Some(Commands::ListProfiles);
-                break;
-            },
+/* get --field: one value printed bare, for scripts, or put on the clipboard with --copy */
+#[derive(Debug)]
+pub struct Pick {
+    pub field: String,      // password, username, email, note, otp, a detail or a custom field
+    pub newline: bool,      // false with --no-newline
+    pub copy: bool,
+}
 
-            /* ******************************************** */
+#[derive(Debug)]
+pub enum TagAction {
+    Add(String),
+    Remove(String),
+}
 
-            "--from" => {
-                if let Some(profile_name) = args.next() {
-                    profile = Some(profile_name);
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
+#[derive(Debug, Clone)]
+pub enum RotationPolicy {
+    Days(u32),
+    Off,        // a record is exempt, a profile has no policy
+    Default,    // a record follows the profile again
+}
 
-            "--init" => {
-                if let Some(profile) = args.next() {
-                    command = Some(Commands::Init(profile));
-                    break;
-                }
-                missing_cmd(cmd);
-            },
+#[derive(Debug)]
+pub enum BackupAction {
+    List,
+    Diff(String),
+    Restore(String),
+}
 
-            "--add" => {
-                if let Some(arg) = args.next() {
-                    command = Some(Commands::Add(arg));
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
+/* ******************************************** */
 
-            "--get" => {
-                if let Some(arg) = args.next() {
-                    command = Some(Commands::Get(arg));
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
-
-            "--list" => {
-                command = Some(Commands::List);
-                continue;
-            },
-
-            "--logs" => {
-                command = Some(Commands::Logs);
-                continue;
-            },
-
-            "--edit" => {
-                if let Some(arg) = args.next() {
-                    command = Some(Commands::Edit(arg));
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
+#[derive(Parser, Debug)]
+#[command(name = "rsafe", version, about = "Encrypted password manager", disable_version_flag = true, arg_required_else_help = true)]
+struct Cli {
+    /// Print version
+    #[arg(short = 'v', long, action = clap::ArgAction::Version)]
+    version: (),
 
-            "--rm" => {
-                if let Some(arg) = args.next() {
-                    command = Some(Commands::Delete(arg));
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
-
-            "--generate" | "-g" => {
-                let size = args.next().as_deref()
-                    .unwrap_or("30")
-                    .parse::<usize>()
-                    .expect("Error: String to number!");
-
-                command = Some(Commands::Generate(size));
-            },
-
-            "--passwd" => {
-                command = Some(Commands::Passwd);
-                continue;
-            },
-
-            "--import" => {
-                if let Some(arg) = args.next() {
-                    command = Some(Commands::Import(arg));
-                    continue;
-                }
-                missing_cmd(cmd);
-            },
+    /// Run the command on this profile instead of the default one
+    #[arg(long, global = true, value_name = "PROFILE")]
+    from: Option<String>,
+
+    /// How results and errors are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Read the master password from this file descriptor instead of the terminal
+    #[arg(long, global = true, value_name = "FD")]
+    master_password_fd: Option<i32>,
+
+    /// Show passwords, hidden fields and other secrets instead of masking them
+    #[arg(long, global = true)]
+    reveal: bool,
+
+    /// Show only this secret: password, a hidden field or a value like cvv (repeatable)
+    #[arg(long = "reveal-field", global = true, value_name = "FIELD")]
+    reveal_fields: Vec<String>,
+
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Create the database with its first profile
+    Init { profile: String },
+
+    /// Print the saved logs
+    Logs,
+
+    /// Add a new entry
+    Add {
+        name: String,
+        /// login, note, card, identity or ssh-key
+        #[arg(long, value_parser = RecordKind::parse, default_value = "login")]
+        kind: RecordKind,
+        /// Tag the entry, can be repeated
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Put the entry in a folder (infra/aws)
+        #[arg(long, value_name = "PATH")]
+        folder: Option<String>,
+        /// Custom field name[:kind]=value, kind is text, hidden, url or number
+        #[arg(long = "field", value_name = "FIELD")]
+        fields: Vec<String>,
+        /// Url of a login as [rule:]url, rule is domain, host, prefix or regex
+        #[arg(long = "url", value_name = "URL")]
+        urls: Vec<String>,
+        #[command(flatten)]
+        values: LoginValues,
+    },
+
+    /// Show the matching entries, passwords masked unless --reveal or --reveal-field is given
+    Get {
+        #[command(flatten)]
+        target: Target,
+        /// Print only this value of a single entry: password, username, email, note, otp,
+        /// a detail like number or cvv, or a custom field
+        #[arg(long, value_name = "FIELD")]
+        field: Option<String>,
+        /// Don't end the value of --field with a newline
+        #[arg(short = 'n', long, requires = "field", conflicts_with = "copy")]
+        no_newline: bool,
+        /// Copy the password (or --field) to the clipboard instead of printing it. It is cleared after
+        /// clipboard_timeout seconds unless something else was copied since; without a clipboard helper
+        /// (OSC 52) the terminal can't be asked what it holds, so it is cleared regardless
+        #[arg(short, long)]
+        copy: bool,
+    },
+
+    /// List the saved entries, or those matching a search
+    List {
+        /// Words that all have to match, field:value, /regex/ and OR, best matches first
+        #[arg(value_name = "SEARCH")]
+        search: Option<String>,
+        #[command(flatten)]
+        filter: Filter,
+    },
+
+    /// Show the entries as a folder tree
+    Tree(Filter),
+
+    /// Add or remove a tag
+    Tag {
+        action: TagOp,
+        #[arg(value_name = "TAG")]
+        label: String,
+        #[command(flatten)]
+        target: Target,
+    },
+
+    /// Move an entry into a folder, '/' is the top level
+    Move {
+        #[arg(value_name = "FOLDER")]
+        destination: String,
+        #[command(flatten)]
+        target: Target,
+    },
+
+    /// Edit an entry, without prompts when --field, --url or --url-rm is given
+    Edit {
+        #[command(flatten)]
+        target: Target,
+        /// Set a custom field name[:kind]=value, an empty value removes it
+        #[arg(long = "field", value_name = "FIELD")]
+        fields: Vec<String>,
+        /// Add a url [rule:]url to a login
+        #[arg(long = "url", value_name = "URL")]
+        urls: Vec<String>,
+        /// Remove a url from a login
+        #[arg(long = "url-rm", value_name = "URL")]
+        remove_urls: Vec<String>,
+        #[command(flatten)]
+        values: LoginValues,
+    },
+
+    /// Remove an entry
+    #[command(alias = "delete")]
+    Rm {
+        #[command(flatten)]
+        target: Target,
+        /// Don't ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+
+    /// Show the logins whose urls match <URL>
+    Lookup { url: String },
+
+    /// Set the rotation policy of an entry, or of the whole profile without a name
+    Rotation {
+        /// A number of days, off or default
+        #[arg(value_parser = parse_policy)]
+        policy: RotationPolicy,
+        name: Option<String>,
+        #[command(flatten)]
+        select: Select,
+    },
+
+    /// Show when an entry changed and its previous passwords
+    History {
+        #[command(flatten)]
+        target: Target,
+        /// Make the n-th previous password current again
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        restore: Option<u64>,
+    },
+
+    /// Show the current one-time code of an entry
+    Otp(Target),
+
+    /// Store an otpauth:// uri or base32 secret on an entry, empty removes it
+    OtpSet(Target),
+
+    /// Encrypt a file and attach it to an entry
+    #[command(override_usage = "rsafe attach <NAME> <FILE>\n       rsafe attach --id <UUID> <FILE>")]
+    Attach {
+        #[arg(num_args = 1..=2, required = true, value_names = ["NAME", "FILE"])]
+        values: Vec<String>,
+        #[command(flatten)]
+        select: Select,
+    },
+
+    /// List the files attached to an entry
+    Attachments(Target),
+
+    /// Decrypt an attachment, to ./<ATTACHMENT> by default
+    #[command(override_usage = "rsafe extract <NAME> <ATTACHMENT> [OUT]\n       rsafe extract --id <UUID> <ATTACHMENT> [OUT]")]
+    Extract {
+        #[arg(num_args = 1..=3, required = true, value_names = ["NAME", "ATTACHMENT", "OUT"])]
+        values: Vec<String>,
+        #[command(flatten)]
+        select: Select,
+    },
+
+    /// Generate a secure password
+    Generate {
+        #[arg(default_value_t = 30)]
+        size: usize,
+    },
+
+    /// Change the master password
+    Passwd,
+
+    /// Import passwords from a csv file
+    Import { path: String },
+
+    /// Export saved passwords to a file
+    Export {
+        // not `format`, that id belongs to the global --format
+        #[arg(value_enum, default_value_t = ExportFormat::Csv, value_name = "FORMAT")]
+        file_format: ExportFormat,
+        /// Also write the one-time password secrets, left out by default
+        #[arg(long)]
+        otp_secrets: bool,
+    },
+
+    /// Tune key derivation for an unlock time in milliseconds
+    KdfTune {
+        #[arg(default_value_t = DEFAULT_UNLOCK_TIME, value_parser = parse_millis)]
+        ms: u128,
+    },
+
+    /// Upgrade the vault to the current file format
+    Migrate {
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// List, compare or restore the automatic backups
+    Backups {
+        #[command(subcommand)]
+        action: Option<BackupCmd>,
+    },
+
+    /// Create, rename, delete or list profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCmd,
+    },
+
+    /// Encrypt profile names and pad record storage
+    SealMetadata,
+
+    /// Store profile names in plaintext again
+    UnsealMetadata,
+
+    /// Print a completion script for bash, zsh or fish
+    Completions { shell: Shell },
+
+    /// Started in the background by get --copy
+    #[command(hide = true)]
+    ClearClipboard { backend: String, seconds: u64 },
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupCmd {
+    /// List the backups
+    List,
+    /// Show what changed since a backup
+    Diff { id: String },
+    /// Replace the vault with a backup
+    Restore { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileCmd {
+    /// Create a new profile
+    Create { name: String },
+    /// Rename a profile
+    Rename { name: String, new_name: String },
+    /// Delete a profile
+    Delete { name: String },
+    /// List all profiles
+    List,
+    /// Set the default profile
+    Default { name: String },
+}
 
-            "--export" => {
-                command = Some(Commands::Export);
-                continue;
-            },
+#[derive(ValueEnum, Clone, Debug)]
+enum TagOp {
+    Add,
+    Rm,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/* Values of a login for add and edit, given any of them nothing is prompted */
+#[derive(Args, Debug)]
+struct LoginValues {
+    /// Username of the login
+    #[arg(long)]
+    username: Option<String>,
+    /// Read the password from the first line of stdin
+    #[arg(long, conflicts_with = "generate_password")]
+    password_stdin: bool,
+    /// Use a random password of this length
+    #[arg(long, value_name = "LEN", value_parser = clap::value_parser!(u16).range(1..))]
+    generate_password: Option<u16>,
+    /// Email of the login, empty clears it on edit
+    #[arg(long)]
+    email: Option<String>,
+    /// Note of the login, empty clears it on edit
+    #[arg(long)]
+    note: Option<String>,
+    /// Don't prompt or ask for confirmation, values not given are left empty (or unchanged on edit)
+    #[arg(long, short = 'y')]
+    yes: bool,
+}
+
+impl LoginValues {
+    fn labels(self) -> Labels {
+        let password = match (self.password_stdin, self.generate_password) {
+            (true, _) => Some(PasswordSource::Stdin),
+            (false, Some(len)) => Some(PasswordSource::Generate(len as usize)),
+            (false, None) => None,
+        };
+
+        Labels {
+            username: self.username,
+            password,
+            email: self.email,
+            note: self.note,
+            yes: self.yes,
+            ..Default::default()
+        }
+    }
+}
+
+/* <name> or --id, for the commands working on one record */
+#[derive(Args, Debug)]
+struct Target {
+    /// Search over the entry name, username, email, note and urls: words that all have to match,
+    /// field:value (user, email, tag, folder, url, kind, field...), /regex/ and OR
+    #[arg(required_unless_present = "id")]
+    name: Option<String>,
+    #[command(flatten)]
+    select: Select,
+}
 
-            "--version" | "-v" => {
-                show_version();
-                return None;
-            },
+#[derive(Args, Debug)]
+struct Select {
+    /// Pick the record by id, a unique prefix is enough
+    #[arg(long, value_name = "UUID")]
+    id: Option<String>,
+    /// Only match <NAME> against the whole entry name
+    #[arg(long)]
+    exact: bool,
+    #[command(flatten)]
+    filter: Filter,
+}
 
+#[derive(Args, Debug)]
+struct Filter {
+    /// Only entries with this tag
+    #[arg(long, value_name = "TAG")]
+    tag: Option<String>,
+    /// Only entries in this folder or below it
+    #[arg(long, value_name = "PATH")]
+    folder: Option<String>,
+    /// Only entries of this kind
+    #[arg(long, value_parser = RecordKind::parse)]
+    kind: Option<RecordKind>,
+    /// Only logins due for rotation within this many days
+    #[arg(long, value_name = "DAYS", value_parser = clap::value_parser!(u32).range(..=rotation::MAX_DAYS as i64))]
+    due: Option<u32>,
+    /// Only logins past their rotation deadline
+    #[arg(long, conflicts_with = "due")]
+    expired: bool,
+}
+
+impl Filter {
+    /* Every Query is made here, so a search that doesn't parse is a usage error like any other */
+    fn query(self, text: String) -> Query {
+        let search = match Search::parse(&text) {
+            Ok(x) => x,
+            Err(err) => exit_with(Cli::command().error(ErrorKind::ValueValidation, err)),
+        };
+
+        Query {
+            text,
+            search,
+            tag: self.tag,
+            folder: self.folder,
+            kind: self.kind,
+            due: if self.expired { Some(0) } else { self.due },
+            ..Default::default()
+        }
+    }
+}
 
-            _ => {}
+impl Select {
+    fn query(self, text: String) -> Query {
+        Query {
+            id: self.id.map(|x| x.to_lowercase()),
+            exact: self.exact,
+            ..self.filter.query(text)
         }
     }
+}
 
-    if command.is_none() {
-        helper(prog_name, Commands::Help);
-        return None;
+impl Target {
+    fn query(self) -> Query {
+        self.select.query(self.name.unwrap_or_default())
     }
+}
 
-    Some((profile, command.unwrap()))
+fn parse_policy(policy: &str) -> Result<RotationPolicy, String> {
+    match policy {
+        "off" => Ok(RotationPolicy::Off),
+        "default" => Ok(RotationPolicy::Default),
+        x => match x.parse::<u32>() {
+            Ok(days) if days > 0 && days <= rotation::MAX_DAYS => Ok(RotationPolicy::Days(days)),
+            _ => Err(format!("expected a number of days up to {}, off or default", rotation::MAX_DAYS)),
+        },
+    }
 }
 
-fn helper(prog_name: String, command: Commands) {
-    if let Commands::Invalid(cmd) = command {
-        let valid_cmds: Vec<&str> = vec![
-            "--version", "--init", "--add", "--get", "--list", "--logs",
-            "--edit", "--rm", "--generate", "--passwd", "--import", "--export",
-            "--set-default-profile", "--from", "--create-profile", "--edit-profile",
-            "--delete-profile", "--list-profiles"
-        ];
+fn parse_millis(ms: &str) -> Result<u128, String> {
+    match ms.parse::<u128>() {
+        Ok(x) if x > 0 => Ok(x),
+        _ => Err("expected an unlock time in milliseconds".to_string()),
+    }
+}
 
-        for valid_cmd in valid_cmds.iter() {
-            if (*valid_cmd).contains(&cmd) {
-                println!("Unknown command '{}'\nSimilar Command '{}' exists", cmd, valid_cmd);
-                return;
+/* ******************************************** */
+
+/* The flags rsafe had before subcommands, and what they are now.
+ * Values after the flag stay with it: `--add github --tag x` -> `add github --tag x` */
+const LEGACY_COMMANDS: &[(&str, &[&str])] = &[
+    ("--init", &["init"]), ("--logs", &["logs"]), ("--add", &["add"]), ("--get", &["get"]),
+    ("--list", &["list"]), ("--tree", &["tree"]), ("--move", &["move"]), ("--edit", &["edit"]),
+    ("--rm", &["rm"]), ("--lookup", &["lookup"]), ("--rotation", &["rotation"]), ("--history", &["history"]),
+    ("--otp", &["otp"]), ("--otp-set", &["otp-set"]), ("--attach", &["attach"]), ("--attachments", &["attachments"]),
+    ("--extract", &["extract"]), ("--generate", &["generate"]), ("-g", &["generate"]), ("--passwd", &["passwd"]),
+    ("--import", &["import"]), ("--export", &["export"]), ("--kdf-tune", &["kdf-tune"]), ("--migrate", &["migrate"]),
+    ("--backups", &["backups"]), ("--seal-metadata", &["seal-metadata"]), ("--unseal-metadata", &["unseal-metadata"]),
+    ("--create-profile", &["profile", "create"]), ("--edit-profile", &["profile", "rename"]),
+    ("--delete-profile", &["profile", "delete"]), ("--list-profiles", &["profile", "list"]),
+    ("--set-profile-default", &["profile", "default"]), ("--set-default-profile", &["profile", "default"]),
+];
+
+/* Options taking a value, so that value isn't mistaken for a subcommand */
+const VALUE_OPTIONS: &[&str] = &[
+    "--from", "--format", "--master-password-fd", "--tag", "--folder", "--kind", "--id", "--field", "--url", "--url-rm",
+    "--due", "--restore", "--username", "--generate-password", "--email", "--note", "--reveal-field",
+];
+
+fn from_legacy(args: Vec<String>) -> Vec<String> {
+    let mut idx = 1;
+    let (start, words) = loop {
+        let arg = match args.get(idx) {
+            Some(x) => x.as_str(),
+            None => {
+                // --expired and --due alone used to mean --list
+                if args.iter().any(|x| x == "--expired" || x == "--due") {
+                    let mut args = args;
+                    args.insert(1, "list".to_string());
+                    return args;
+                }
+                return args;
             }
+        };
+
+        if !arg.starts_with('-') {
+            return args;        // already a subcommand
+        }
+
+        // --tag add|rm <tag> changed tags, any other --tag is a filter
+        let tag_cmd = arg == "--tag" && matches!(args.get(idx + 1).map(|x| x.as_str()), Some("add" | "rm"));
+        if tag_cmd {
+            break (idx, &["tag"][..]);
+        }
+        if let Some((_, words)) = LEGACY_COMMANDS.iter().find(|(flag, _)| *flag == arg) {
+            break (idx, *words);
         }
 
-        println!("Unknown command '{}'\nTry '{} --help' for usage.", cmd, prog_name);
-        return;
+        idx += if VALUE_OPTIONS.contains(&arg) { 2 } else { 1 };
+    };
+
+    let end = (start + 1..args.len())
+        .find(|x| args[*x].starts_with('-'))
+        .unwrap_or(args.len());
+
+    let mut out = vec![args[0].clone()];
+    out.extend(words.iter().map(|x| x.to_string()));
+    out.extend_from_slice(&args[start + 1..end]);
+    out.extend_from_slice(&args[1..start]);
+    out.extend_from_slice(&args[end..]);
+    out
+}
+
+/* --format is needed before clap is done, its own errors follow it too */
+fn early_format(args: &[String]) -> Format {
+    let value = args.iter()
+        .position(|x| x == "--format")
+        .and_then(|idx| args.get(idx + 1).cloned())
+        .or(args.iter().find_map(|x| x.strip_prefix("--format=").map(|x| x.to_string())));
+
+    value.and_then(|x| Format::from_str(&x, true).ok()).unwrap_or_default()
+}
+
+/* Help and version go to stdout, anything else is a usage error in the chosen format */
+fn exit_with(err: clap::Error) -> ! {
+    if !err.use_stderr() || output::format() == Format::Table {
+        err.exit();
     }
 
-    if let Commands::Help = command {
-        println!("Usage:");
-        println!("  {} <command> [options]", prog_name);
-
-        println!("\nCommands:");
-        println!("  --version                     Displays current version");
-        println!("  --init <profile>              Initiates the database");
-        println!("  --logs                        Prints the saved logs");
-        println!("  --add <name>                  Add a new password entry");
-        println!("  --get <name>                  Retrieve a password");
-        println!("  --list                        List all saved entries");
-        println!("  --edit <name>                 Edit an entry");
-        println!("  --rm <name>                   Remove an entry");
-        println!("  --generate <size>             Generate a secure password");
-        println!("  --passwd                      Change master password");
-        println!("  --import <path>               Import passwords from a file");
-        println!("  --export                      Export saved passwords to a file");
-
-        println!("\nProfile Commands:");
-        println!("  --create-profile <name>       Create a new profile");
-        println!("  --edit-profile <name>         Rename or modify a profile");
-        println!("  --delete-profile <name>       Delete a profile");
-        println!("  --list-profiles               List all profiles");
-        println!("  --set-default-profile <name>  Set default profile");
-
-        println!("\nProfile Usage:");
-        println!("  --from <name>                 Execute command using specified profile");
+    // "error: ..." and the "tip: ..." lines, without the usage
+    let rendered = err.render().to_string();
+    let message = rendered.lines()
+        .map(|x| x.trim())
+        .take_while(|x| !x.starts_with("Usage:"))
+        .filter(|x| !x.is_empty())
+        .map(|x| x.trim_start_matches("error: "))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    output::error(ErrorCode::Usage, message);
+    std::process::exit(ErrorCode::Usage as i32);
+}
+
+pub fn parse_args(args: impl Iterator<Item = String>) -> Option<(Globals, Commands)> {
+    let args = from_legacy(args.collect());
+    output::set_format(early_format(&args));
+
+    let cli = match Cli::try_parse_from(args) {
+        Ok(x) => x,
+        Err(err) => exit_with(err),
+    };
+    let missing = |msg: &str| -> ! {
+        exit_with(Cli::command().error(ErrorKind::MissingRequiredArgument, msg))
+    };
+
+    let command = match cli.command {
+        Cmd::Init { profile } => Commands::Init(profile),
+        Cmd::Logs => Commands::Logs,
+        Cmd::Add { name, kind, tags, folder, fields, urls, values } => {
+            Commands::Add((name, Labels { kind, tags, folder, fields, urls, ..values.labels() }))
+        },
+        Cmd::Get { target, field, no_newline, copy } => {
+            let field = match copy {
+                true => Some(field.unwrap_or("password".to_string())),
+                false => field,
+            };
+            Commands::Get((target.query(), field.map(|field| Pick { field, newline: !no_newline, copy })))
+        },
+        Cmd::List { search, filter } => Commands::List(filter.query(search.unwrap_or_default())),
+        Cmd::Tree(filter) => Commands::Tree(filter.query(String::new())),
+        Cmd::Tag { action, label, target } => {
+            let action = match action {
+                TagOp::Add => TagAction::Add(label),
+                TagOp::Rm => TagAction::Remove(label),
+            };
+            Commands::Tag((action, target.query()))
+        },
+        Cmd::Move { destination, target } => Commands::Move((destination, target.query())),
+        Cmd::Edit { target, fields, urls, remove_urls, values } => {
+            Commands::Edit((target.query(), Labels { fields, urls, remove_urls, ..values.labels() }))
+        },
+        Cmd::Rm { target, yes } => Commands::Delete((target.query(), yes)),
+        Cmd::Lookup { url } => Commands::Lookup(url),
+
+        // without a name (or --id) the policy is the profile's
+        Cmd::Rotation { policy, name, select } => Commands::Rotation((policy, select.query(name.unwrap_or_default()))),
+        Cmd::History { target, restore } => Commands::History((target.query(), restore.map(|x| x as usize))),
+        Cmd::Otp(target) => Commands::Otp(target.query()),
+        Cmd::OtpSet(target) => Commands::OtpSet(target.query()),
+
+        // with --id there is no <name>, the values move one place to the left
+        Cmd::Attach { mut values, select } => {
+            let file = values.pop().unwrap_or_default();
+            let text = values.pop().unwrap_or_default();
+            if text.is_empty() && select.id.is_none() {
+                missing("attach needs <NAME> <FILE>, or --id <UUID> <FILE>");
+            }
+            Commands::Attach((select.query(text), file))
+        },
+        Cmd::Attachments(target) => Commands::Attachments(target.query()),
+        Cmd::Extract { mut values, select } => {
+            if select.id.is_none() && values.len() < 2 {
+                missing("extract needs <NAME> <ATTACHMENT>, or --id <UUID> <ATTACHMENT>");
+            }
+            if select.id.is_some() && values.len() == 3 {
+                exit_with(Cli::command().error(ErrorKind::ArgumentConflict, "<NAME> can't be used with --id"));
+            }
+            let text = if select.id.is_none() { values.remove(0) } else { String::new() };
+            let attachment = values.remove(0);
+            Commands::Extract((select.query(text), attachment, values.pop()))
+        },
+
+        Cmd::Generate { size } => Commands::Generate(size),
+        Cmd::Passwd => Commands::Passwd,
+        Cmd::Import { path } => Commands::Import(path),
+        Cmd::Export { file_format, otp_secrets } => Commands::Export((matches!(file_format, ExportFormat::Json), otp_secrets)),
+        Cmd::KdfTune { ms } => Commands::KdfTune(ms),
+        Cmd::Migrate { dry_run } => Commands::Migrate(dry_run),
+        Cmd::Backups { action } => Commands::Backups(match action {
+            Some(BackupCmd::List) | None => BackupAction::List,
+            Some(BackupCmd::Diff { id }) => BackupAction::Diff(id),
+            Some(BackupCmd::Restore { id }) => BackupAction::Restore(id),
+        }),
+        Cmd::Profile { action } => match action {
+            ProfileCmd::Create { name } => Commands::CreateProfile(name),
+            ProfileCmd::Rename { name, new_name } => Commands::EditProfile((name, new_name)),
+            ProfileCmd::Delete { name } => Commands::DeleteProfile(name),
+            ProfileCmd::List => Commands::ListProfiles,
+            ProfileCmd::Default { name } => Commands::Default(name),
+        },
+        Cmd::SealMetadata => Commands::SealMetadata,
+        Cmd::UnsealMetadata => Commands::UnsealMetadata,
+        Cmd::Completions { shell } => Commands::Completions(shell),
+        Cmd::ClearClipboard { backend, seconds } => Commands::ClearClipboard((backend, seconds)),
+    };
+
+    if cli.reveal {
+        output::set_reveal(output::Reveal::All);
+    } else if !cli.reveal_fields.is_empty() {
+        output::set_reveal(output::Reveal::Fields(cli.reveal_fields));
     }
+
+    Some((Globals { profile: cli.from, password_fd: cli.master_password_fd }, command))
 }
 
-fn show_version() {
-    println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+pub fn print_completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), "rsafe", &mut io::stdout());
 }
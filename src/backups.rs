@@ -0,0 +1,171 @@
+use std::{
+    fs, path::{Path, PathBuf}
+};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, Utc};
+
+use crate::config::{self, Config};
+
+/* One saved version of dump.json in ~/.rustsafe/backups, named dump-<id>.json */
+pub struct Backup {
+    pub id: String,
+    pub time: DateTime<Local>,
+    pub size: u64,
+    pub path: PathBuf,
+}
+
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S%3f";     // utc, sorts in time order
+
+fn backup_dir(vault_path: &str) -> PathBuf {
+    Path::new(vault_path)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("backups")
+}
+
+/* Keeps a copy of the vault as it was just written, then drops whatever retention doesn't cover */
+pub fn snapshot(vault_path: &str) -> Result<(), String> {
+    let config = config::get();
+    if config.backup_count == 0 {
+        return Ok(());
+    }
+
+    let dir = backup_dir(vault_path);
+    fs::create_dir_all(&dir).map_err(|e| format!("Error creating {}: {e}", dir.display()))?;
+
+    let id = Utc::now().format(ID_FORMAT).to_string();
+    let backup = dir.join(format!("dump-{}.json", id));
+    fs::copy(vault_path, &backup).map_err(|e| format!("Error writing {}: {e}", backup.display()))?;
+
+    prune(vault_path, config)
+}
+
+/* Newest first */
+pub fn list(vault_path: &str) -> Result<Vec<Backup>, String> {
+    let dir = backup_dir(vault_path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(_) => return Ok(Vec::new()),     // nothing was backed up yet
+    };
+
+    let mut backups: Vec<Backup> = entries
+        .filter_map(|x| x.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = name.strip_prefix("dump-")?.strip_suffix(".json")?.to_string();
+            let time = NaiveDateTime::parse_from_str(&id, ID_FORMAT).ok()?.and_utc().with_timezone(&Local);
+            let size = entry.metadata().ok()?.len();
+
+            Some(Backup { id, time, size, path: entry.path() })
+        })
+        .collect();
+
+    backups.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(backups)
+}
+
+/* `id` is either the full id or its number in --backups list */
+pub fn find(vault_path: &str, id: &str) -> Result<Backup, String> {
+    let mut backups = list(vault_path)?;
+
+    let pos = match id.parse::<usize>() {
+        Ok(x) if x >= 1 && x <= backups.len() => Some(x - 1),
+        _ => backups.iter().position(|x| x.id == id),
+    };
+
+    match pos {
+        Some(x) => Ok(backups.swap_remove(x)),
+        None => Err(format!("No backup '{}' exists, see --backups list", id)),
+    }
+}
+
+/* The newest backup_count backups are kept, minus those older than backup_max_age days (0 = no limit),
+ * the newest one always survives so there is something to restore */
+fn prune(vault_path: &str, config: &Config) -> Result<(), String> {
+    let max_age = TimeDelta::days(config.backup_max_age as i64);
+    let now = Local::now();
+
+    for (idx, backup) in list(vault_path)?.iter().enumerate() {
+        let too_many = idx >= config.backup_count;
+        let too_old = idx > 0 && config.backup_max_age > 0 && now - backup.time > max_age;
+
+        if too_many || too_old {
+            fs::remove_file(&backup.path).map_err(|e| format!("Error removing {}: {e}", backup.path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn temp_vault(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("rsafe-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("backups")).unwrap();
+        dir.join("dump.json").to_string_lossy().to_string()
+    }
+
+    /* A backup saved `days` ago */
+    fn saved(path: &str, days: i64) -> String {
+        let id = (Utc::now() - TimeDelta::days(days)).format(ID_FORMAT).to_string();
+        fs::write(backup_dir(path).join(format!("dump-{}.json", id)), b"{}").unwrap();
+        id
+    }
+
+    fn ids(path: &str) -> Vec<String> {
+        list(path).unwrap().into_iter().map(|x| x.id).collect()
+    }
+
+    #[test]
+    fn only_the_newest_backups_are_kept() {
+        let path = temp_vault("backups-count");
+        let saved: Vec<String> = (0..5).map(|x| saved(&path, x)).collect();
+        fs::write(backup_dir(&path).join("notes.txt"), b"not a backup").unwrap();
+
+        prune(&path, &Config { backup_count: 3, backup_max_age: 0, ..Default::default() }).unwrap();
+        assert_eq!(ids(&path), saved[..3]);
+        assert!(backup_dir(&path).join("notes.txt").exists());
+
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn old_backups_expire_but_the_newest_is_kept() {
+        let path = temp_vault("backups-age");
+        let recent = saved(&path, 1);
+        saved(&path, 40);
+        saved(&path, 100);
+
+        prune(&path, &Config { backup_count: 10, backup_max_age: 30, ..Default::default() }).unwrap();
+        assert_eq!(ids(&path), [recent]);
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+
+        // even when it is past the limit itself
+        let path = temp_vault("backups-age-newest");
+        let newest = saved(&path, 100);
+        saved(&path, 200);
+
+        prune(&path, &Config { backup_count: 10, backup_max_age: 30, ..Default::default() }).unwrap();
+        assert_eq!(ids(&path), [newest]);
+
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn backups_are_found_by_number_or_id() {
+        let path = temp_vault("backups-find");
+        let older = saved(&path, 2);
+        let newer = saved(&path, 1);
+
+        assert_eq!(find(&path, "1").unwrap().id, newer);
+        assert_eq!(find(&path, "2").unwrap().id, older);
+        assert_eq!(find(&path, &older).unwrap().id, older);
+        assert!(find(&path, "3").is_err());
+        assert!(find(&path, "0").is_err());
+
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+}
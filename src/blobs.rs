@@ -0,0 +1,158 @@
+use std::{
+    collections::HashSet, fs::{self, OpenOptions}, io::Write, path::{Path, PathBuf}
+};
+use aes_gcm::{
+    aead::Aead, Aes256Gcm, Key, KeyInit, Nonce,
+};
+use hex::{encode, decode};
+use rand::random;
+use uuid::Uuid;
+
+use crate::storage;
+
+/* Attachments live in ~/.rustsafe/blobs, one file per attachment named by a random id.
+ * Each file is nonce + aes-gcm ciphertext under its own random key, the key is only kept
+ * in the (encrypted) record so changing the master password doesn't touch the blobs. */
+fn blob_dir(vault_path: &str) -> PathBuf {
+    Path::new(vault_path)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("blobs")
+}
+
+/* Ids are always the hyphenated lowercase form of a uuid, anything else (a path, `..`) never reaches the file system */
+pub fn check_id(id: &str) -> Result<(), String> {
+    match Uuid::try_parse(id) {
+        Ok(x) if x.hyphenated().to_string() == id => Ok(()),
+        _ => Err(format!("Invalid attachment id '{}'", id)),
+    }
+}
+
+fn blob_path(vault_path: &str, id: &str) -> Result<PathBuf, String> {
+    check_id(id)?;
+    Ok(blob_dir(vault_path).join(format!("{}.bin", id)))
+}
+
+/* Encrypts `data` into a new blob, returns its id and hex key */
+pub fn store(vault_path: &str, data: &[u8]) -> Result<(String, String), String> {
+    let dir = blob_dir(vault_path);
+    fs::create_dir_all(&dir).map_err(|e| format!("Error creating {}: {e}", dir.display()))?;
+
+    let id = Uuid::new_v4().to_string();
+    let key: [u8; 32] = random();
+    let nonce: [u8; 12] = random();
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let mut sealed = nonce.to_vec();
+    sealed.extend(cipher.encrypt(Nonce::from_slice(&nonce), data).map_err(|e| format!("Error encrypting attachment: {e}"))?);
+
+    let path = blob_path(vault_path, &id)?;
+    storage::write_atomic(&path.to_string_lossy(), &sealed)?;
+    Ok((id, encode(key)))
+}
+
+pub fn load(vault_path: &str, id: &str, key: &str) -> Result<Vec<u8>, String> {
+    let path = blob_path(vault_path, id)?;
+    let sealed = fs::read(&path).map_err(|e| format!("Error reading {}: {e}", path.display()))?;
+
+    if sealed.len() < 12 {
+        return Err(format!("{} is truncated", path.display()));
+    }
+
+    let key = decode(key).map_err(|e| format!("Invalid attachment key: {e}"))?;
+    if key.len() != 32 {
+        return Err("Invalid attachment key".to_string());
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    cipher.decrypt(Nonce::from_slice(&sealed[..12]), &sealed[12..])
+        .map_err(|_| format!("{} failed to decrypt, it was modified or belongs to another record", path.display()))
+}
+
+/* Writes an extracted attachment, never over an existing file and readable by the owner only */
+pub fn write_out(path: &str, data: &[u8]) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path).map_err(|e| format!("Error creating {}: {e}", path))?;
+    file.write_all(data).map_err(|e| format!("Error writing {}: {e}", path))
+}
+
+/* Removes every blob no profile references anymore, returns how many were removed */
+pub fn sweep(vault_path: &str, referenced: &HashSet<String>) -> Result<usize, String> {
+    let dir = blob_dir(vault_path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(_) => return Ok(0),     // no attachment was ever added
+    };
+
+    let mut removed = 0;
+    for entry in entries.filter_map(|x| x.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = match name.strip_suffix(".bin") {
+            Some(x) if check_id(x).is_ok() => x,
+            _ => continue,       // temp files of a write in progress, .bak, files rsafe didn't write...
+        };
+
+        if !referenced.contains(id) {
+            fs::remove_file(entry.path()).map_err(|e| format!("Error removing {}: {e}", entry.path().display()))?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn temp_vault(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("rsafe-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("dump.json").to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn only_uuids_are_ids() {
+        assert!(check_id(&Uuid::new_v4().to_string()).is_ok());
+        for id in ["../dump", "/etc/passwd", "", "..", "3F2504E0-4F89-11D3-9A0C-0305E82C3301", "3f2504e04f8911d39a0c0305e82c3301"] {
+            assert!(check_id(id).is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn blobs_round_trip_under_their_own_key() {
+        let path = temp_vault("blobs");
+        let (id, key) = store(&path, b"ssh key").unwrap();
+
+        assert_eq!(load(&path, &id, &key).unwrap(), b"ssh key");
+        assert!(load(&path, &id, &encode([0u8; 32])).is_err());
+        assert!(load(&path, "../dump", &key).is_err());
+
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn sweep_keeps_referenced_and_foreign_files() {
+        let path = temp_vault("sweep");
+        let (kept, _) = store(&path, b"kept").unwrap();
+        let (dropped, _) = store(&path, b"dropped").unwrap();
+        fs::write(blob_dir(&path).join("notes.bin"), b"not a blob").unwrap();
+
+        assert_eq!(sweep(&path, &HashSet::from([kept.clone()])).unwrap(), 1);
+        assert!(blob_path(&path, &kept).unwrap().exists());
+        assert!(!blob_path(&path, &dropped).unwrap().exists());
+        assert!(blob_dir(&path).join("notes.bin").exists());
+
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    env, fs::OpenOptions, io::{Read, Write}, process::{Command, Stdio}, thread, time::Duration
+};
+use data_encoding::BASE64;
+use sha2::{Sha256, Digest};
+
+/* Where get --copy puts a value: the first clipboard helper found for the session,
+ * or the OSC 52 escape sequence which the terminal itself turns into a copy (works over ssh) */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Wayland,    // wl-copy / wl-paste
+    Xclip,
+    Xsel,
+    Pbcopy,     // macOS
+    Osc52,
+}
+
+const BACKENDS: [(Backend, &str); 5] = [
+    (Backend::Wayland, "wayland"),
+    (Backend::Xclip, "xclip"),
+    (Backend::Xsel, "xsel"),
+    (Backend::Pbcopy, "pbcopy"),
+    (Backend::Osc52, "osc52"),
+];
+
+impl Backend {
+    fn detect() -> Self {
+        let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = env::var_os("DISPLAY").is_some();
+
+        if wayland && on_path("wl-copy") && on_path("wl-paste") {
+            Backend::Wayland
+        } else if x11 && on_path("xclip") {
+            Backend::Xclip
+        } else if x11 && on_path("xsel") {
+            Backend::Xsel
+        } else if on_path("pbcopy") && on_path("pbpaste") {
+            Backend::Pbcopy
+        } else {
+            Backend::Osc52
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        BACKENDS.iter().find(|(x, _)| x == self).map(|(_, name)| *name).unwrap_or_default()
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        BACKENDS.iter()
+            .find(|(_, x)| *x == name)
+            .map(|(backend, _)| *backend)
+            .ok_or(format!("Unknown clipboard '{}'", name))
+    }
+
+    fn copy_command(&self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Backend::Wayland => Some(("wl-copy", &[])),
+            Backend::Xclip => Some(("xclip", &["-selection", "clipboard"])),
+            Backend::Xsel => Some(("xsel", &["--clipboard", "--input"])),
+            Backend::Pbcopy => Some(("pbcopy", &[])),
+            Backend::Osc52 => None,
+        }
+    }
+
+    fn paste_command(&self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Backend::Wayland => Some(("wl-paste", &["--no-newline"])),
+            Backend::Xclip => Some(("xclip", &["-selection", "clipboard", "-o"])),
+            Backend::Xsel => Some(("xsel", &["--clipboard", "--output"])),
+            Backend::Pbcopy => Some(("pbpaste", &[])),
+            Backend::Osc52 => None,
+        }
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+fn digest(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/* Puts `value` on the clipboard and returns the backend that took it */
+pub fn copy(value: &str) -> Result<Backend, String> {
+    let backend = Backend::detect();
+    write(backend, value)?;
+    Ok(backend)
+}
+
+fn write(backend: Backend, value: &str) -> Result<(), String> {
+    let (program, args) = match backend.copy_command() {
+        Some(x) => x,
+        None => return osc52(value),
+    };
+
+    // xclip and xsel stay around to serve the selection, their output must not be waited on
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Running {}: {e}", program))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(value.as_bytes()).map_err(|e| format!("Writing to {}: {e}", program))?;
+    }
+
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", program, status)),
+        Err(e) => Err(format!("Running {}: {e}", program)),
+    }
+}
+
+fn read(backend: Backend) -> Option<String> {
+    let (program, args) = backend.paste_command()?;
+    let output = Command::new(program).args(args).stderr(Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/* Written to the terminal rather than stdout, so `get --copy > file` still copies.
+ * tmux only passes it on wrapped in its own escape (and with set-clipboard on) */
+fn osc52(value: &str) -> Result<(), String> {
+    let sequence = format!("\x1b]52;c;{}\x07", BASE64.encode(value.as_bytes()));
+    let sequence = match env::var_os("TMUX") {
+        Some(_) => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+        None => sequence,
+    };
+
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| format!("No clipboard helper was found and the terminal can't be written to: {e}"))?;
+    tty.write_all(sequence.as_bytes()).and_then(|_| tty.flush()).map_err(|e| e.to_string())
+}
+
+/* Starts `rsafe clear-clipboard` in the background, it outlives this process and the vault lock.
+ * Only the sha256 of the value is handed over, on its stdin */
+pub fn schedule_clear(backend: Backend, value: &str, seconds: u64) -> Result<(), String> {
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let mut child = Command::new(exe)
+        .args(["clear-clipboard", backend.name(), &seconds.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Starting the clipboard timer: {e}"))?;
+
+    let mut stdin = child.stdin.take().ok_or("Starting the clipboard timer")?;
+    stdin.write_all(digest(value).as_bytes()).map_err(|e| e.to_string())
+}
+
+/* The background half of schedule_clear. The terminal can't be asked what it holds,
+ * so with OSC 52 the clipboard is cleared whatever is in it by then */
+pub fn clear_after(backend: Backend, seconds: u64) -> Result<(), String> {
+    let mut expected = String::new();
+    std::io::stdin().read_to_string(&mut expected).map_err(|e| e.to_string())?;
+
+    thread::sleep(Duration::from_secs(seconds));
+
+    if backend != Backend::Osc52 {
+        match read(backend) {
+            Some(current) if digest(&current) == expected.trim() => {},
+            _ => return Ok(()),     // something else was copied since
+        }
+    }
+
+    match backend {
+        Backend::Wayland => Command::new("wl-copy").arg("--clear").status()
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        _ => write(backend, ""),
+    }
+}
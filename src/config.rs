@@ -0,0 +1,53 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    fs, sync::OnceLock
+};
+
+/* User settings from ~/.rustsafe/config.json, every field is optional */
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub lock_timeout: u64,      // seconds to wait for another rsafe process, 0 fails right away
+    pub backup_count: usize,    // versions kept in ~/.rustsafe/backups, 0 turns backups off
+    pub backup_max_age: u64,    // days, 0 keeps them regardless of age
+    pub history_size: usize,    // previous passwords kept per record
+    pub reveal_secrets: bool,   // show passwords and other secrets in tables without --reveal
+    pub clipboard_timeout: u64, // seconds before get --copy clears the clipboard, 0 leaves it
+}
+
+pub const DEFAULT_LOCK_TIMEOUT: u64 = 10;
+pub const DEFAULT_BACKUP_COUNT: usize = 20;
+pub const DEFAULT_BACKUP_MAX_AGE: u64 = 90;
+pub const DEFAULT_HISTORY_SIZE: usize = 10;
+pub const DEFAULT_CLIPBOARD_TIMEOUT: u64 = 30;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            backup_count: DEFAULT_BACKUP_COUNT,
+            backup_max_age: DEFAULT_BACKUP_MAX_AGE,
+            history_size: DEFAULT_HISTORY_SIZE,
+            reveal_secrets: false,
+            clipboard_timeout: DEFAULT_CLIPBOARD_TIMEOUT,
+        }
+    }
+}
+
+/* A missing file means defaults, a broken one is reported instead of silently ignored */
+pub fn init(path: &str) -> Result<(), String> {
+    let config = match fs::read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid config file {}: {e}", path))?,
+        Err(_) => Config::default(),
+    };
+
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
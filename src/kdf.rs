@@ -0,0 +1,260 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::HashMap, sync::{Mutex, OnceLock}, time::Instant
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hex::encode;
+use hmac::{Mac, Hmac};
+use hmac::digest::KeyInit as HmacKeyInit;
+use hkdf::Hkdf;
+use rand::random;
+use sha2::{Sha256, Digest};
+
+/* Argon2id parameters stored per profile in the DumpFile.
+ * Checked when deserialized, an edited dump.json could otherwise ask for terabytes of memory */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(try_from = "StoredKdfParams")]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: String,
+    pub memory: u32,        // KiB
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/* KdfParams as read, before KdfParams::check */
+#[derive(Deserialize)]
+struct StoredKdfParams {
+    algorithm: String,
+    salt: String,
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+/* How the keys for each purpose (record hmacs, key check, wrapped vault key, index...) are made from
+ * a derived key. Stored next to whatever they key and left out when sha256, so files written before
+ * hkdf read (and mac) exactly as they did until they are rewritten */
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Subkeys {
+    #[default]
+    Sha256,     // sha256("rsafe-<label>:<key>")
+    Hkdf,       // hkdf-sha256 of the key with "rsafe-<label>" as info
+}
+
+pub const ARGON2ID: &str = "argon2id";
+pub const DEFAULT_MEMORY: u32 = 64 * 1024;
+pub const DEFAULT_ITERATIONS: u32 = 3;
+pub const DEFAULT_PARALLELISM: u32 = 1;
+pub const DEFAULT_UNLOCK_TIME: u128 = 1000;     // milliseconds
+
+pub const MIN_MEMORY: u32 = 8 * 1024;
+const MAX_MEMORY: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 16;
+const MAX_PARALLELISM: u32 = 4;
+const KEY_LEN: usize = 32;
+
+/* derived keys are cached so load + dump in one command only pay for the kdf once.
+ * Entries are found by an hmac of the parameters and the password under a key that only
+ * lives in this process, the master password itself is never kept */
+static DERIVED: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+static CACHE_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+impl TryFrom<StoredKdfParams> for KdfParams {
+    type Error = String;
+
+    fn try_from(x: StoredKdfParams) -> Result<Self, String> {
+        let params = KdfParams {
+            algorithm: x.algorithm, salt: x.salt, memory: x.memory, iterations: x.iterations, parallelism: x.parallelism,
+        };
+        params.check()?;
+        Ok(params)
+    }
+}
+
+impl KdfParams {
+    pub fn new(memory: u32, iterations: u32, parallelism: u32) -> Self {
+        let salt: Vec<u8> = (0..16).map(|_| { random::<u8>() }).collect();
+
+        KdfParams {
+            algorithm: ARGON2ID.to_string(),
+            salt: encode(salt),
+            memory,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /* The costs --kdf-tune can pick, anything else didn't come from rsafe */
+    fn check(&self) -> Result<(), String> {
+        if !(MIN_MEMORY..=MAX_MEMORY).contains(&self.memory) {
+            return Err(format!("Invalid kdf memory {} KiB, expected {} to {} KiB", self.memory, MIN_MEMORY, MAX_MEMORY));
+        }
+        if !(1..=MAX_ITERATIONS).contains(&self.iterations) {
+            return Err(format!("Invalid kdf iterations {}, expected 1 to {}", self.iterations, MAX_ITERATIONS));
+        }
+        if !(1..=MAX_PARALLELISM).contains(&self.parallelism) {
+            return Err(format!("Invalid kdf parallelism {}, expected 1 to {}", self.parallelism, MAX_PARALLELISM));
+        }
+        Ok(())
+    }
+
+    /* Same cost settings with a fresh salt, used when the master password changes */
+    pub fn resalt(&self) -> Self {
+        KdfParams::new(self.memory, self.iterations, self.parallelism)
+    }
+
+    /* Returns the hex encoded 32 byte key for the master password */
+    pub fn derive(&self, master: &str) -> Result<String, String> {
+        let cache_key = self.cache_key(master);
+        if let Some(key) = DERIVED.lock().unwrap().as_ref().and_then(|x| x.get(&cache_key)) {
+            return Ok(key.clone());
+        }
+
+        let key = self.derive_uncached(master)?;
+        DERIVED.lock().unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(cache_key, key.clone());
+
+        Ok(key)
+    }
+
+    fn cache_key(&self, master: &str) -> String {
+        let secret = CACHE_KEY.get_or_init(random);
+        let mut mac = <Hmac<Sha256> as HmacKeyInit>::new_from_slice(secret).expect("[!] Error: Creating hmac");
+        mac.update(format!("{}:{}:{}:{}:{}\0", self.algorithm, self.salt, self.memory, self.iterations, self.parallelism).as_bytes());
+        mac.update(master.as_bytes());
+        encode(mac.finalize().into_bytes())
+    }
+
+    fn derive_uncached(&self, master: &str) -> Result<String, String> {
+        if self.algorithm != ARGON2ID {
+            return Err(format!("Unsupported key derivation '{}'", self.algorithm));
+        }
+
+        let salt = hex::decode(&self.salt).map_err(|e| format!("{e}"))?;
+        let params = Params::new(self.memory, self.iterations, self.parallelism, Some(KEY_LEN))
+            .map_err(|e| format!("Invalid kdf parameters: {e}"))?;
+
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(master.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Key derivation failed: {e}"))?;
+
+        Ok(encode(key))
+    }
+}
+
+impl Subkeys {
+    pub fn is_sha256(&self) -> bool {
+        *self == Subkeys::Sha256
+    }
+
+    pub fn derive(&self, label: &str, key: &str) -> [u8; KEY_LEN] {
+        let mut subkey = [0u8; KEY_LEN];
+        match self {
+            Subkeys::Sha256 => subkey.copy_from_slice(&Sha256::digest(format!("rsafe-{}:{}", label, key).as_bytes())),
+            Subkeys::Hkdf => Hkdf::<Sha256>::new(None, key.as_bytes())
+                .expand(format!("rsafe-{}", label).as_bytes(), &mut subkey)
+                .expect("[!] Error: 32 bytes is a valid hkdf length"),
+        }
+        subkey
+    }
+
+    /* Key of a single record, `salt` is the record's 24 hex chars */
+    pub fn record(&self, salt: &str, key: &str) -> [u8; KEY_LEN] {
+        let mut subkey = [0u8; KEY_LEN];
+        match self {
+            /* hash(salt[..12] + key + salt[12..]) */
+            Subkeys::Sha256 => subkey.copy_from_slice(&Sha256::digest(format!("{}{}{}", &salt[..12], key, &salt[12..]).as_bytes())),
+            Subkeys::Hkdf => Hkdf::<Sha256>::new(Some(salt.as_bytes()), key.as_bytes())
+                .expand(b"rsafe-record", &mut subkey)
+                .expect("[!] Error: 32 bytes is a valid hkdf length"),
+        }
+        subkey
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::new(DEFAULT_MEMORY, DEFAULT_ITERATIONS, DEFAULT_PARALLELISM)
+    }
+}
+
+/* Time (in ms) a single derivation takes with the given cost, kept out of the cache */
+fn benchmark(memory: u32, iterations: u32, parallelism: u32) -> Result<u128, String> {
+    let params = KdfParams::new(memory, iterations, parallelism);
+    let start = Instant::now();
+    params.derive_uncached(&params.salt)?;
+    Ok(start.elapsed().as_millis().max(1))
+}
+
+/* Picks the most expensive parameters that still unlock in about `target` ms on this machine.
+ * Memory is raised first (that is what hurts gpu crackers), then iterations fill the rest. */
+pub fn tune(target: u128) -> Result<(KdfParams, u128), String> {
+    let parallelism = std::thread::available_parallelism()
+        .map(|x| x.get() as u32)
+        .unwrap_or(1)
+        .clamp(1, MAX_PARALLELISM);
+
+    let mut memory = DEFAULT_MEMORY;
+    let mut elapsed = benchmark(memory, 1, parallelism)?;
+
+    while elapsed > target && memory > MIN_MEMORY {
+        memory /= 2;
+        elapsed = benchmark(memory, 1, parallelism)?;
+    }
+
+    while elapsed * 2 <= target && memory < MAX_MEMORY {
+        memory *= 2;
+        elapsed = benchmark(memory, 1, parallelism)?;
+    }
+
+    let iterations = ((target / elapsed) as u32).clamp(1, MAX_ITERATIONS);
+    let elapsed = benchmark(memory, iterations, parallelism)?;
+
+    Ok((KdfParams::new(memory, iterations, parallelism), elapsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn stored(memory: u32, iterations: u32, parallelism: u32) -> Value {
+        serde_json::to_value(KdfParams::new(memory, iterations, parallelism)).unwrap()
+    }
+
+    #[test]
+    fn params_in_range_load() {
+        for value in [stored(MIN_MEMORY, 1, 1), stored(DEFAULT_MEMORY, DEFAULT_ITERATIONS, DEFAULT_PARALLELISM), stored(MAX_MEMORY, MAX_ITERATIONS, MAX_PARALLELISM)] {
+            assert!(serde_json::from_value::<KdfParams>(value).is_ok());
+        }
+    }
+
+    #[test]
+    fn params_out_of_range_are_refused() {
+        let cases = [
+            stored(MIN_MEMORY - 1, 1, 1), stored(MAX_MEMORY + 1, 1, 1), stored(u32::MAX, 1, 1),
+            stored(MIN_MEMORY, 0, 1), stored(MIN_MEMORY, MAX_ITERATIONS + 1, 1),
+            stored(MIN_MEMORY, 1, 0), stored(MIN_MEMORY, 1, MAX_PARALLELISM + 1),
+        ];
+        for value in cases {
+            assert!(serde_json::from_value::<KdfParams>(value.clone()).is_err(), "{value}");
+        }
+
+        let mut value = stored(MIN_MEMORY, 1, 1);
+        value["memory"] = json!(-1);
+        assert!(serde_json::from_value::<KdfParams>(value).is_err());
+    }
+
+    #[test]
+    fn same_password_and_salt_give_the_same_key() {
+        let params = KdfParams::new(MIN_MEMORY, 1, 1);
+
+        assert_eq!(params.derive("hunter2").unwrap(), params.derive_uncached("hunter2").unwrap());
+        assert_ne!(params.derive("hunter2").unwrap(), params.derive("hunter3").unwrap());
+        assert_ne!(params.derive("hunter2").unwrap(), params.resalt().derive("hunter2").unwrap());
+    }
+}
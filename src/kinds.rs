@@ -0,0 +1,159 @@
+use serde::{Serialize, Deserialize};
+
+use crate::output;
+
+/* What a record holds, decides the prompts of --add/--edit and the columns of --list/--get.
+ * Logins keep using username/password/email/note, every other kind keeps its values in `details` */
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordKind {
+    #[default]
+    Login,
+    Note,
+    Card,
+    Identity,
+    SshKey,
+}
+
+/* How a value is asked for */
+pub enum Input {
+    Line,
+    Hidden,     // not echoed
+    Lines,      // until an empty line
+    File,       // a path, the file contents are stored
+}
+
+pub struct KindField {
+    pub name: &'static str,     // key in Record.details
+    pub label: &'static str,    // prompt text and column header
+    pub input: Input,
+    pub secret: bool,           // masked in --list
+    pub required: bool,
+}
+
+const fn field(name: &'static str, label: &'static str, input: Input, secret: bool, required: bool) -> KindField {
+    KindField { name, label, input, secret, required }
+}
+
+const NOTE: &[KindField] = &[
+    field("text", "note", Input::Lines, false, true),
+];
+
+const CARD: &[KindField] = &[
+    field("holder", "cardholder", Input::Line, false, false),
+    field("number", "card number", Input::Line, true, true),
+    field("expiry", "expiry (MM/YY)", Input::Line, false, true),
+    field("cvv", "cvv", Input::Hidden, true, false),
+    field("pin", "pin", Input::Hidden, true, false),
+];
+
+const IDENTITY: &[KindField] = &[
+    field("full_name", "full name", Input::Line, false, true),
+    field("birth_date", "birth date", Input::Line, false, false),
+    field("email", "email", Input::Line, false, false),
+    field("phone", "phone", Input::Line, false, false),
+    field("address", "address", Input::Line, false, false),
+    field("document", "passport / id number", Input::Line, true, false),
+];
+
+const SSH_KEY: &[KindField] = &[
+    field("private_key", "private key", Input::File, true, true),
+    field("public_key", "public key", Input::File, false, false),
+    field("passphrase", "passphrase", Input::Hidden, true, false),
+];
+
+impl RecordKind {
+    pub fn parse(kind: &str) -> Result<Self, String> {
+        match kind.trim().to_lowercase().as_str() {
+            "login" => Ok(RecordKind::Login),
+            "note" | "secure-note" => Ok(RecordKind::Note),
+            "card" | "credit-card" => Ok(RecordKind::Card),
+            "identity" => Ok(RecordKind::Identity),
+            "ssh" | "ssh-key" => Ok(RecordKind::SshKey),
+            x => Err(format!("Unknown record kind '{}', expected login, note, card, identity or ssh-key", x)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordKind::Login => "login",
+            RecordKind::Note => "note",
+            RecordKind::Card => "card",
+            RecordKind::Identity => "identity",
+            RecordKind::SshKey => "ssh-key",
+        }
+    }
+
+    /* Empty for logins, they have their own columns */
+    pub fn schema(&self) -> &'static [KindField] {
+        match self {
+            RecordKind::Login => &[],
+            RecordKind::Note => NOTE,
+            RecordKind::Card => CARD,
+            RecordKind::Identity => IDENTITY,
+            RecordKind::SshKey => SSH_KEY,
+        }
+    }
+}
+
+/* Checks a value typed for `field` and returns it the way it is stored */
+pub fn validate(field: &str, value: &str) -> Result<String, String> {
+    match field {
+        "number" => {
+            let digits: String = value.chars().filter(|x| !x.is_whitespace() && *x != '-').collect();
+            if !(12..=19).contains(&digits.len()) || !digits.chars().all(|x| x.is_ascii_digit()) {
+                return Err("A card number has 12 to 19 digits".to_string());
+            }
+            if !luhn(&digits) {
+                return Err("The card number fails its checksum, check for a typo".to_string());
+            }
+            Ok(digits)
+        },
+        "expiry" => {
+            let (month, year) = value.split_once('/').ok_or("Expected the expiry as MM/YY")?;
+            let month = month.trim().parse::<u32>().map_err(|_| "Expected the expiry as MM/YY")?;
+            let year = year.trim();
+
+            if !(1..=12).contains(&month) || !(year.len() == 2 || year.len() == 4) || year.parse::<u32>().is_err() {
+                return Err("Expected the expiry as MM/YY".to_string());
+            }
+            Ok(format!("{:02}/{}", month, &year[year.len() - 2..]))
+        },
+        "cvv" | "pin" => match value.chars().all(|x| x.is_ascii_digit()) && (3..=8).contains(&value.len()) {
+            true => Ok(value.to_string()),
+            false => Err(format!("The {} is 3 to 8 digits", field)),
+        },
+        "private_key" => match value.contains("PRIVATE KEY") {
+            true => Ok(value.to_string()),
+            false => Err("That file doesn't look like a private key".to_string()),
+        },
+        _ => Ok(value.to_string()),
+    }
+}
+
+/* Value as shown in a table, secrets are masked unless revealed (card numbers keep their last 4 digits) */
+pub fn display(field: &KindField, value: &str) -> String {
+    if !field.secret || output::reveals(field.name) {
+        return value.to_string();
+    }
+
+    match field.name {
+        "number" if value.len() > 4 => format!("•••• {}", &value[value.len() - 4..]),
+        _ => output::MASK.to_string(),
+    }
+}
+
+fn luhn(digits: &str) -> bool {
+    let sum: u32 = digits.chars()
+        .rev()
+        .filter_map(|x| x.to_digit(10))
+        .enumerate()
+        .map(|(idx, x)| match idx % 2 {
+            1 if x * 2 > 9 => x * 2 - 9,
+            1 => x * 2,
+            _ => x,
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
@@ -6,6 +6,7 @@ use std::{
 };
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum LogType {
     ERROR,
     DEBUG,
@@ -74,8 +75,9 @@ fn give_ban() {
         let _ = writeln!(file, "{} BAN User Banned for {} milliseconds", time, BAN_TIME);
     }
 
-    println!(
-        "[?] You have been banned for {} minutes.\nPlease wait until your ban expires before trying again.",
+    crate::fail!(
+        Auth,
+        "You have been banned for {} minutes.\nPlease wait until your ban expires before trying again.",
         (BAN_TIME / (1000 * 60))
     );
 }
@@ -90,8 +92,9 @@ pub fn ban_if_invalid(logs: Vec<LogType>) -> bool {
 
     let last_log = logs.last().unwrap_or(&LogType::DEBUG);
     if let Some(time) = time_till_unban(last_log) {
-        println!(
-            "[?] You are still banned. Time remaining: {} minutes and {} seconds.",
+        crate::fail!(
+            Auth,
+            "You are still banned. Time remaining: {} minutes and {} seconds.",
             (time / (1000 * 60)),
             (time / 1000) % 60
         );
@@ -118,12 +121,10 @@ pub fn get_last_logs(n: usize, file: &str) -> Option<Vec<LogType>> {
             if x.contains("DEBUG") {
                 return LogType::DEBUG;
             }
-            if x.contains("BAN") {
-                if let Some(time) = x.split_whitespace().nth(0) {
-                    if let Ok(_t) = time.parse::<u128>() {
-                        return LogType::BAN(_t);
-                    }
-                }
+            if x.contains("BAN")
+                && let Some(time) = x.split_whitespace().next()
+                && let Ok(_t) = time.parse::<u128>() {
+                return LogType::BAN(_t);
             }
             if x.contains("INFO") {
                 return LogType::INFO;
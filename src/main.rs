@@ -1,13 +1,28 @@
 /* Modules */
+#[macro_use]
+mod output;
 mod vault;
+mod kdf;
+mod migrate;
+mod storage;
+mod config;
+mod backups;
+mod blobs;
+mod kinds;
+mod urls;
+mod rotation;
+mod otp;
 mod logger;
 mod argparse;
+mod clipboard;
+mod search;
 
 /* Imports */
 use std::{
-    collections::HashMap, env::{self}, fs::{self, OpenOptions}, io::Read
+    env::{self}, fs::{self, OpenOptions}, io::{IsTerminal, Read, Write}, time::Duration
 };
 use std::sync::OnceLock;
+use std::collections::BTreeMap;
 use csv::{Reader, Writer};
 use tabled::{Table, Tabled, settings::Style};
 
@@ -18,33 +33,43 @@ static PATH: OnceLock<String> = OnceLock::new();
 static PASSWORDFILE: OnceLock<String> = OnceLock::new();
 static EXPORTFILE: OnceLock<String> = OnceLock::new();
 static LOG_FILE: OnceLock<String> = OnceLock::new();
+static LOCK_FILE: OnceLock<String> = OnceLock::new();
+static CONFIG_FILE: OnceLock<String> = OnceLock::new();
+static MASTER_PASSWORD: OnceLock<String> = OnceLock::new();    // --master-password-fd / RSAFE_MASTER_PASSWORD_FILE
 
 type Commands = argparse::Commands;
+type Query = argparse::Query;
 
 fn main() {
+    run();
+    std::process::exit(output::exit_code());
+}
+
+fn run() {
     if set_paths().is_none() {
-        println!("[!] Error: In setting paths!");
+        fail!(Failure, "Error: In setting paths!");
         return;
     }
 
-    let (profile, command) = match argparse::parse_args(env::args()) {
+    let (globals, command) = match argparse::parse_args(env::args()) {
         Some(x) => x,
         None => return
     };
+    let profile = globals.profile;
 
     match command {
         Commands::Init(profile) => {
             if fs::exists(PATH.get().unwrap()).unwrap() {
-                let val = format!("[+] DataBase Already Exists!");
-                println!("{}", val);
+                let val = "DataBase Already Exists!".to_string();
+                fail!(Failure, "{}", val);
                 log!(ERROR, val);
                 return;
             }
 
             match initialize_database(profile) {
-                Ok(()) => println!("[+] Database created successfully"),
+                Ok(()) => say!("[+] Database created successfully"),
                 Err(x) => {
-                    println!("[!] Error: {x}");
+                    fail!(Failure, "Error: {x}");
                     log!(ERROR, x.to_string());
                 }
             }
@@ -53,37 +78,98 @@ fn main() {
         Commands::Logs => print_logs(),
 
         Commands::Generate(size) => {
-            let data: String = format!("[$] Generated Password -> {}", vault::generate_rand_password(size));
-            println!("{}", data);
-            log!(LOG_FILE.get().unwrap());
-            log!(DEBUG, data);
+            let password = vault::generate_rand_password(size);
+            let data: String = format!("[$] Generated Password -> {}", password);
+            match output::format() {
+                output::Format::Table => println!("{}", data),
+                output::Format::Plain => println!("{}", password),
+                output::Format::Json => output::json(serde_json::json!({ "password": password })),
+            }
+            // nothing to log into before init
+            if fs::exists(PATH.get().unwrap()).unwrap_or(false) {
+                log!(LOG_FILE.get().unwrap());
+                log!(DEBUG, data);
+            }
+        },
+
+        Commands::Completions(shell) => argparse::print_completions(shell),
+
+        // nothing to print to, it runs detached from the get --copy that started it
+        Commands::ClearClipboard((backend, seconds)) => {
+            if let Err(err) = clipboard::Backend::parse(&backend).and_then(|x| clipboard::clear_after(x, seconds)) {
+                fail!(Failure, "Error: {err}");
+            }
         },
 
         _ => {
 
             if !fs::exists(PATH.get().unwrap()).unwrap() {
-                println!("[!] Database isn't created.\nTry '{} --init' to create a database",
-                    env::args()
-                    .nth(0)
+                fail!(NotFound, "Database isn't created.\nTry '{} init <profile>' to create a database",
+                    env::args().next()
                     .unwrap_or("".to_string())
                 );
                 return;
             }
 
-            if let false = log!(LOG_FILE.get().unwrap()) {
+            if !log!(LOG_FILE.get().unwrap()) {
                 // user is banned probably
                 return;
             }
 
+            if let Err(err) = config::init(CONFIG_FILE.get().unwrap()) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+
+            if let Err(err) = read_master_password(globals.password_fd) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+
+            // held until the command returns, covers every load -> modify -> dump
+            let _lock = match storage::lock_vault(
+                LOCK_FILE.get().unwrap(), Duration::from_secs(config::get().lock_timeout)
+            ) {
+                Ok(x) => x,
+                Err(err) => {
+                    fail!(Locked, "Error: {err}");
+                    log!(ERROR, err);
+                    return;
+                }
+            };
+
             match command {
-                Commands::Add(entry) => store_new_credential(entry, profile.as_ref()),
-                Commands::Get(entry) => display_stored_credentials(Some(entry), profile.as_ref()),
-                Commands::List => display_stored_credentials(None, profile.as_ref()),
-                Commands::Edit(entry) => update_existing_credential(entry, profile.as_ref()),
-                Commands::Delete(entry) => remove_existing_credential(entry, profile.as_ref()),
+                Commands::Add((entry, labels)) => store_new_credential(entry, labels, profile.as_ref()),
+                Commands::Get((query, pick)) => match pick {
+                    Some(pick) => print_record_field(query, pick, profile.as_ref()),
+                    None => display_stored_credentials(query, false, profile.as_ref()),
+                },
+                Commands::List(query) => display_stored_credentials(query, true, profile.as_ref()),
+                Commands::Tree(query) => display_folder_tree(query, profile.as_ref()),
+                Commands::Tag((action, query)) => update_record_labels(query, Some(action), None, profile.as_ref()),
+                Commands::Move((folder, query)) => update_record_labels(query, None, Some(folder), profile.as_ref()),
+                Commands::Edit((query, labels)) => update_existing_credential(query, labels, profile.as_ref()),
+                Commands::Lookup(url) => lookup_url(url, profile.as_ref()),
+                Commands::Rotation((policy, query)) => set_rotation_policy(policy, query, profile.as_ref()),
+                Commands::Delete((query, yes)) => remove_existing_credential(query, yes, profile.as_ref()),
+                Commands::History((query, restore)) => password_history(query, restore, profile.as_ref()),
+                Commands::Otp(query) => show_otp_code(query, profile.as_ref()),
+                Commands::OtpSet(query) => set_otp_secret(query, profile.as_ref()),
+                Commands::Attach((query, file)) => attach_file(query, file, profile.as_ref()),
+                Commands::Attachments(query) => list_attachments(query, profile.as_ref()),
+                Commands::Extract((query, name, out)) => extract_attachment(query, name, out, profile.as_ref()),
                 Commands::Passwd => update_master_password(profile.as_ref()),
                 Commands::Import(path) => import_credentials_from_json(path, profile.as_ref()),
-                Commands::Export => export_credentials_to_json(),
+                Commands::Export((json, otp_secrets)) => export_credentials_to_json(json, otp_secrets),
+                Commands::KdfTune(target) => tune_key_derivation(target, profile.as_ref()),
+                Commands::Migrate(dry_run) => migrate_vault(dry_run),
+                Commands::Backups(action) => match action {
+                    argparse::BackupAction::List => list_backups(),
+                    argparse::BackupAction::Diff(id) => diff_backup(id, profile.as_ref()),
+                    argparse::BackupAction::Restore(id) => restore_backup(id),
+                },
+                Commands::SealMetadata => seal_metadata(),
+                Commands::UnsealMetadata => unseal_metadata(),
 
                 /* Profile Setup */
                 Commands::Default(profile) => set_default_profile(profile),
@@ -99,18 +185,23 @@ fn main() {
 
 fn set_default_profile(profile: String) {
     let path = PASSWORDFILE.get().unwrap();
-    let mut dump = match DumpFile::load_dumpfile(&path) {
+    let mut dump = match DumpFile::load_dumpfile(path) {
         Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            fail!(Failure, "Error: {err}");
             return;
         }
     };
 
-    dump.default = Some(profile);
+    let index_key = index_password(&dump);
+
+    if let Err(err) = dump.set_default(&profile, index_key.as_deref()) {
+        report_load_error(err);
+        return;
+    }
 
-    if let Err(err) = dump.dump_dumpfile(&path) {
-        eprintln!("[!] Error: {err}");
+    if let Err(err) = dump.dump_dumpfile(path) {
+        fail!(Failure, "Error: {err}");
     }
 
     log!(INFO, "Default profile was changed");
@@ -118,23 +209,40 @@ fn set_default_profile(profile: String) {
 
 fn create_profile(profile: String) {
     let path = PASSWORDFILE.get().unwrap();
-    let mut dump = match DumpFile::load_dumpfile(&path) {
+    let mut dump = match DumpFile::load_dumpfile(path) {
         Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            fail!(Failure, "Error: {err}");
             return;
         }
     };
 
-    if dump.profiles.get(&profile).is_none() {
-        dump.profiles.insert(profile, Vec::new());
-    } else {
-        eprintln!("[!] Error: Profile '{}' already exists!", profile);
+    let index_key = update_password(&dump);
+
+    match dump.contains_profile(&profile, index_key.as_deref()) {
+        Ok(true) => {
+            fail!(Failure, "Error: Profile '{}' already exists!", profile);
+            return;
+        },
+        Ok(false) => {},
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    }
+
+    let password = match prompt_new_password() {
+        Some(x) => x,
+        None => return,
+    };
+
+    if let Err(err) = dump.add_profile(&profile, &password, index_key.as_deref()) {
+        report_load_error(err);
         return;
     }
 
-    if let Err(err) = dump.dump_dumpfile(&path) {
-        eprintln!("[!] Error: {err}");
+    if let Err(err) = dump.dump_dumpfile(path) {
+        fail!(Failure, "Error: {err}");
     }
 
     log!(INFO, "A new profile was created");
@@ -142,32 +250,30 @@ fn create_profile(profile: String) {
 
 fn edit_profile_name(old_profile: String, profile: String) {
     let path = PASSWORDFILE.get().unwrap();
-    let mut dump = match DumpFile::load_dumpfile(&path) {
+    let dump = match DumpFile::load_dumpfile(path) {
         Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            fail!(Failure, "Error: {err}");
             return;
         }
     };
 
-    if let Some(value) = dump.profiles.get(&old_profile) {
-        let new_value = value.clone();
-        dump.profiles.remove(&old_profile);
-
-        dump.profiles.insert(profile, new_value);
-    } else {
-        eprintln!("[!] Error: No profile '{}' exists with that name", old_profile);
+    // sealed names can only be checked once the password is known, rename_profile reports it then
+    if !dump.is_sealed() && !dump.profiles.contains_key(&old_profile) {
+        fail!(NotFound, "Error: No profile '{}' exists with that name", old_profile);
         return;
     }
 
-    if let Some(ref default_profile) = dump.default {
-        if default_profile.eq(&old_profile) {
-            dump.default = None;
-        }
-    }
+    let password = match prompt_master_password(
+        format!("[+] Enter master password for `{}` profile: ", old_profile), Some(&old_profile)
+    ) {
+        Some(x) => x,
+        None => return,
+    };
 
-    if let Err(err) = dump.dump_dumpfile(&path) {
-        eprintln!("[!] Error: {err}");
+    if let Err(err) = vault::rename_profile(path, &password, &old_profile, &profile) {
+        report_load_error(err);
+        return;
     }
 
     log!(INFO, "A profile was edited");
@@ -181,9 +287,9 @@ struct Profiles {
 }
 
 impl Profiles {
-    fn get_profiles(raw_data: HashMap<String, Vec<vault::Record>>) -> Vec<Profiles> {
+    fn get_profiles(raw_data: Vec<String>) -> Vec<Profiles> {
         raw_data
-            .into_keys()
+            .into_iter()
             .enumerate()
             .map(|(idx, data)|
                 Profiles {
@@ -196,54 +302,368 @@ impl Profiles {
 
 fn list_profiles() {
     let path = PASSWORDFILE.get().unwrap();
-    let profiles = match DumpFile::load_dumpfile(&path) {
-        Ok(x) => x.profiles,
+    let dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let index_key = index_password(&dump);
+
+    let (names, default) = match dump.profile_names(index_key.as_deref())
+        .and_then(|x| Ok((x, dump.default_profile(index_key.as_deref())?))) {
+        Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            report_load_error(err);
             return;
         }
     };
 
-    let profiles = Profiles::get_profiles(profiles);
+    match output::format() {
+        output::Format::Json => {
+            let profiles: Vec<serde_json::Value> = names.iter()
+                .map(|x| serde_json::json!({ "name": x, "default": default.as_ref() == Some(x) }))
+                .collect();
+            return output::json(profiles);
+        },
+        output::Format::Plain => {
+            names.iter().for_each(|x| println!("{}", x));
+            return;
+        },
+        output::Format::Table => {},
+    }
 
+    let profiles = Profiles::get_profiles(names);
     let mut table = Table::new(profiles);
     table.with(Style::rounded());
 
-    println!("{table}");
+    say!("{table}");
 }
 
 fn delete_profile(profile: String) {
     let path = PASSWORDFILE.get().unwrap();
-    let mut dump = match DumpFile::load_dumpfile(&path) {
+    let mut dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let index_key = update_password(&dump);
+
+    match dump.contains_profile(&profile, index_key.as_deref()) {
+        Ok(true) => {
+            ask!("[+] Profile '{}' found. Do you want to delete it ? (Y/N)", profile);
+            if 'n'.eq_ignore_ascii_case(&fgets().chars().next().unwrap_or('n')) {
+                say!("[$] Profile '{}' wasn't deleted!", profile);
+                return;
+            }
+        },
+        Ok(false) => {
+            fail!(NotFound, "Error: No profile '{}' exists with that name", profile);
+            return;
+        },
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    }
+
+    if let Err(err) = dump.remove_profile(&profile, index_key.as_deref()) {
+        report_load_error(err);
+        return;
+    }
+
+    if let Err(err) = dump.dump_dumpfile(path) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[$] Profile '{}' was deleted!", profile);
+    log!(INFO, "A profile was deleted");
+}
+
+/* Scripts hand the master password over a file descriptor or a file instead of the terminal,
+ * only the first line is used */
+fn read_master_password(fd: Option<i32>) -> Result<(), String> {
+    let path = match fd {
+        Some(fd) => format!("/dev/fd/{}", fd),
+        None => match env::var("RSAFE_MASTER_PASSWORD_FILE") {
+            Ok(x) if !x.is_empty() => x,
+            _ => return Ok(()),
+        },
+    };
+
+    let data = fs::read_to_string(&path).map_err(|e| format!("Reading the master password from {}: {e}", path))?;
+    let password = data.lines().next().unwrap_or_default();
+    if password.is_empty() {
+        return Err(format!("{} doesn't hold a master password", path));
+    }
+
+    let _ = MASTER_PASSWORD.set(password.to_string());
+    Ok(())
+}
+
+/* Profiles without a key check yet get their password chosen here instead of accepting anything */
+fn prompt_master_password(prompt: String, profile: Option<&String>) -> Option<String> {
+    if let Some(password) = MASTER_PASSWORD.get() {
+        return Some(password.clone());
+    }
+
+    let has_password = DumpFile::load_dumpfile(PASSWORDFILE.get().unwrap())
+        .and_then(|x| x.has_password(profile));
+
+    if let Ok(false) = has_password {
+        say!("[#] This profile has no master password yet, please choose one");
+        return prompt_new_password();
+    }
+
+    Some(rpassword::prompt_password(prompt).unwrap())
+}
+
+/* Sealed vaults hide the profile names, any profile's master password unlocks them */
+fn index_password(dump: &DumpFile) -> Option<String> {
+    if !dump.is_sealed() {
+        return None;
+    }
+
+    if let Some(password) = MASTER_PASSWORD.get() {
+        return Some(password.clone());
+    }
+
+    Some(rpassword::prompt_password("[+] Enter a master password to unlock the profile index: ").unwrap())
+}
+
+/* Adding or removing a profile rewrites the mac over all of them, which takes any master password */
+fn update_password(dump: &DumpFile) -> Option<String> {
+    if dump.is_sealed() || !dump.has_mac() {
+        return index_password(dump);
+    }
+
+    if let Some(password) = MASTER_PASSWORD.get() {
+        return Some(password.clone());
+    }
+
+    Some(rpassword::prompt_password("[+] Enter the master password of any profile to update the vault: ").unwrap())
+}
+
+fn prompt_new_password() -> Option<String> {
+    let passwd: String = rpassword::prompt_password("[+] Enter new master password: ").unwrap();
+    let _password: String = rpassword::prompt_password("[+] Enter new master password again: ").unwrap();
+
+    if passwd != _password {
+        fail!(Input, "Passwords doesn't match!");
+        return None;
+    }
+
+    Some(passwd)
+}
+
+fn report_load_error(err: vault::VaultError) {
+    match err {
+        vault::VaultError::WrongPassword => {
+            fail!(Auth, "Incorrect Password");
+            log!(INVALID, "Incorrect Password");
+        },
+        vault::VaultError::Integrity { .. } | vault::VaultError::FileIntegrity => {
+            fail!(Integrity, "{err}");
+            log!(ERROR, err.to_string());
+        },
+        vault::VaultError::Other(x) => fail!(Failure, "Error: {x}"),
+    }
+}
+
+/* Indexes of the records a query selects: by tag and folder, then by id prefix, exact entry name or the search,
+ * best matches first. Fuzzy matches only count when nothing matches the search as written */
+fn find_records(records: &[vault::Record], query: &Query) -> Vec<usize> {
+    let filtered: Vec<usize> = records.iter()
+        .enumerate()
+        .filter(|(_, record)| query.tag.as_ref().is_none_or(|x| record.tags().contains(&x.to_lowercase())))
+        .filter(|(_, record)| query.folder.as_ref().is_none_or(|x| record.in_folder(x)))
+        .filter(|(_, record)| query.kind.is_none_or(|x| record.kind() == x))
+        .map(|(idx, _)| idx)
+        .collect();
+
+    if let Some(id) = &query.id {
+        return filtered.into_iter().filter(|x| records[*x].id().starts_with(id.as_str())).collect();
+    }
+    if query.exact {
+        let entry = query.text.to_lowercase();
+        return filtered.into_iter().filter(|x| records[*x].entry().to_lowercase() == entry).collect();
+    }
+
+    let ranked = |fuzzy: bool| {
+        let mut found: Vec<(u32, usize)> = filtered.iter()
+            .map(|x| (query.search.score(&records[*x], fuzzy), *x))
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        // stable, equally good matches keep the vault order
+        found.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        found.into_iter().map(|(_, idx)| idx).collect::<Vec<usize>>()
+    };
+
+    match ranked(false) {
+        found if found.is_empty() && !query.search.is_empty() => ranked(true),
+        found => found,
+    }
+}
+
+/* Without prompts there is nobody to pick, more than one match is an error */
+fn single_record(records: &[vault::Record], found: &[usize], query: &Query) -> Option<usize> {
+    if let [idx] = found {
+        return Some(*idx);
+    }
+
+    fail!(Input, "{} records match {}, use --id or --exact to pick one:", found.len(), describe_query(query));
+    for idx in found {
+        eprintln!("  {} ({}) {}", records[*idx].entry(), records[*idx].username(), records[*idx].id());
+    }
+    None
+}
+
+/* --password-stdin reads one line, --generate-password makes one up */
+fn resolve_password(source: Option<argparse::PasswordSource>) -> Result<Option<String>, String> {
+    match source {
+        Some(argparse::PasswordSource::Stdin) => {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map_err(|e| format!("Reading the password from stdin: {e}"))?;
+
+            match line.trim_end_matches(['\r', '\n']) {
+                "" => Err("No password was given on stdin".to_string()),
+                x => Ok(Some(x.to_string())),
+            }
+        },
+        Some(argparse::PasswordSource::Generate(len)) => Ok(Some(vault::generate_rand_password(len))),
+        None => Ok(None),
+    }
+}
+
+/* One record out of the matches, the user picks when there is more than one */
+fn pick_record(records: &[vault::Record], found: &[usize]) -> Option<usize> {
+    if let [idx] = found {
+        return Some(*idx);
+    }
+
+    say!("[#] {} records match:", found.len());
+    for (n, idx) in found.iter().enumerate() {
+        let record = &records[*idx];
+        say!("  {}) {} ({}) {}", n + 1, record.entry(), record.username(), record.id());
+    }
+
+    ask!("[+] Select a record (1-{}): ", found.len());
+    match vault::fgets().parse::<usize>() {
+        Ok(n) if (1..=found.len()).contains(&n) => Some(found[n - 1]),
+        _ => {
+            fail!(Input, "Invalid choice, nothing was changed");
+            None
+        }
+    }
+}
+
+fn describe_query(query: &Query) -> String {
+    let mut description = match &query.id {
+        Some(id) => format!("id '{}'", id),
+        None if query.text.is_empty() => "the filter".to_string(),
+        None => format!("the phrase '{}'", query.text),
+    };
+
+    if let Some(tag) = &query.tag {
+        description.push_str(&format!(" tagged '{}'", tag));
+    }
+    if let Some(folder) = &query.folder {
+        description.push_str(&format!(" in folder '{}'", folder));
+    }
+    description
+}
+
+fn migrate_vault(dry_run: bool) {
+    let path = PASSWORDFILE.get().unwrap();
+    let mut value = match DumpFile::load_raw(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let version = migrate::version(&value);
+    let report = match migrate::migrate(&mut value) {
         Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            fail!(Failure, "Error: {err}");
+            log!(ERROR, err);
             return;
         }
     };
 
-    if dump.profiles.get(&profile).is_some() {
-        print!("[+] Profile '{}' found. Do you want to delete it ? (Y/N)", profile);
-        if 'n' == fgets().chars().nth(0).unwrap().to_ascii_lowercase() {
-            println!("[$] Profile '{}' wasn't deleted!", profile);
+    /* profiles of older vaults that the file mac doesn't cover yet, adding them takes every password */
+    let uncovered = match serde_json::from_value::<DumpFile>(value) {
+        Ok(x) => x.uncovered_profiles(),
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
             return;
         }
+    };
 
-        dump.profiles.remove(&profile);
-        println!("[$] Profile '{}' was deleted!", profile);
+    let json = serde_json::json!({
+        "from": version, "to": migrate::FORMAT_VERSION, "steps": report, "uncovered": uncovered, "dry_run": dry_run
+    });
+    if output::format() != output::Format::Json {
+        match report.is_empty() {
+            true => say!("[+] Vault is already at format v{}", version),
+            false => for line in &report {
+                say!("{line}");
+            },
+        }
+        if !uncovered.is_empty() {
+            say!("[#] Not covered by the vault mac yet: {}", uncovered.join(", "));
+        }
     }
 
-    if let Some(ref def_profile) = dump.default {
-        if def_profile.eq(&profile) {
-            dump.default = None;
+    if dry_run {
+        match output::format() {
+            output::Format::Json => output::json(json),
+            _ => say!("[#] Dry run, nothing was written"),
         }
+        return;
     }
 
-    if let Err(err) = dump.dump_dumpfile(&path) {
-        eprintln!("[!] Error: {err}");
+    /* loading applies the same steps and writes the file after taking a backup */
+    let dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            log!(ERROR, err);
+            return;
+        }
+    };
+
+    if !uncovered.is_empty() {
+        say!("[#] Every profile has to be unlocked once to cover it with the vault mac");
+        let mut names: Vec<String> = dump.profiles.keys().cloned().collect();
+        names.sort();
+
+        let unlocked = match unlock_profiles(path, names) {
+            Some(x) => x,
+            None => return,
+        };
+        if let Err(err) = vault::cover_profiles(path, &unlocked) {
+            report_load_error(err);
+            return;
+        }
+        say!("[+] Every profile is covered by the vault mac");
+        log!(INFO, "Every profile was covered by the vault mac");
     }
 
-    log!(INFO, "A profile was deleted");
+    if output::format() == output::Format::Json {
+        output::json(json);
+    }
+    if !report.is_empty() {
+        log!(INFO, format!("Vault was migrated from format v{} to v{}", version, migrate::FORMAT_VERSION));
+    }
 }
 
 fn set_paths() -> Option<()> {
@@ -252,14 +672,16 @@ fn set_paths() -> Option<()> {
     PASSWORDFILE.set(format!("{}/.rustsafe/dump.json", dir_name.display())).ok()?;
     EXPORTFILE.set(format!("{}", dir_name.display())).ok()?;
     LOG_FILE.set(format!("{}/.rustsafe/log", dir_name.display())).ok()?;
+    LOCK_FILE.set(format!("{}/.rustsafe/lock", dir_name.display())).ok()?;
+    CONFIG_FILE.set(format!("{}/.rustsafe/config.json", dir_name.display())).ok()?;
 
     Some(())
 }
 
 fn print_logs() {
     if !fs::exists(LOG_FILE.get().unwrap()).unwrap() {
-        let val = format!("[!] Error: Log file wasn't created!");
-        println!("{}", val);
+        let val = "Error: Log file wasn't created!".to_string();
+        fail!(NotFound, "{}", val);
         log!(ERROR, val);
         return;
     }
@@ -272,22 +694,23 @@ fn print_logs() {
 
         let mut buffer = String::new();
         let _ = file.read_to_string(&mut buffer).unwrap();
-        println!("{}", buffer);
+        say!("{}", buffer);
     }
 
     log!(INFO, "Logs were viewed");
 }
 
 fn initialize_database(profile: String) -> std::result::Result<(), String> {
+    let password = prompt_new_password().ok_or("Database wasn't created")?;
+
     fs::create_dir(PATH.get().unwrap()).map_err(|e| e.to_string())?;
     let path = PASSWORDFILE.get().unwrap();
     let _ = fs::File::create(path).map_err(|e| e.to_string())?;
     log!(LOG_FILE.get().unwrap());
 
-    let mut profiles = HashMap::new();
-    profiles.insert(profile.clone(), Vec::new());
-
-    let file = DumpFile { default: Some(profile), profiles: profiles };
+    let mut file = DumpFile::new();
+    file.add_profile(&profile, &password, None).map_err(|e| e.to_string())?;
+    file.default = Some(profile);
 
     file.dump_dumpfile(path).map_err(|e| e.to_string())?;
 
@@ -295,65 +718,60 @@ fn initialize_database(profile: String) -> std::result::Result<(), String> {
     Ok(())
 }
 
-fn display_stored_credentials(entry: Option<String>, profile: Option<&String>) {
-    let password: String = rpassword::prompt_password("[+] Enter master password: ").unwrap();
+fn display_stored_credentials(query: Query, list: bool, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
 
     let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
         Ok(y) => match y {
             Some(x) => x,
             None => {
-                println!("[!] No records were found!\nTry 'rustsafe --add' to create a new record");
+                fail!(NotFound, "No records were found!\nTry 'rustsafe --add' to create a new record");
                 return;
             }
         },
         Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            } else {
-                println!("[!] Error: {err}");
-            }
+            report_load_error(err);
             return;
         }
     };
 
-    if entry.is_none() {        // if list is called
+    if let Some(days) = query.due {
+        return display_due_records(records, query, days, &password, profile);
+    }
+    overdue_warning(&records, &password, profile);
+
+    if list {
         if records.is_empty() {
-            println!("[!] No passwords were saved!\nTry 'rustsafe --add' to create a new record");
+            fail!(NotFound, "No passwords were saved!\nTry 'rustsafe --add' to create a new record");
             log!(INFO, "All Records were viewed but database empty");
             return;
         }
-        vault::record_fmt(vault::RecordPrint::VECTOR(records));
-        log!(INFO, "All Records were viewed");
-        return;
-    }
 
-    let search = entry.unwrap_or(String::from(""));
-    let mut found = Vec::new();
-
-    for record in records {
-        if record.entry().to_lowercase().contains(&search) || record.username().to_lowercase().contains(&search) {
-            found.push(record);
-            continue;
-        }
+        let found: Vec<vault::Record> = find_records(&records, &query)
+            .into_iter()
+            .map(|idx| records[idx].clone())
+            .collect();
 
-        if let Some(_email) = record.email() {
-            if _email.to_lowercase().contains(&search) {
-                found.push(record);
-                continue;
-            }
+        if found.is_empty() {
+            fail!(NotFound, "No records match {}", describe_query(&query));
+            return;
         }
 
-        if let Some(note) = record.note() {
-            if note.to_lowercase().contains(&search) {
-                found.push(record);
-                continue;
-            }
-        }
+        vault::record_fmt(vault::RecordPrint::VECTOR(found));
+        log!(INFO, "All Records were viewed");
+        return;
     }
 
+    let found: Vec<vault::Record> = find_records(&records, &query)
+        .into_iter()
+        .map(|idx| records[idx].clone())
+        .collect();
+
     if found.is_empty() {
-        println!("[!] Record with '{}' doesn't exists", search);
+        fail!(NotFound, "Record with {} doesn't exists", describe_query(&query));
         return;
     }
 
@@ -361,135 +779,571 @@ fn display_stored_credentials(entry: Option<String>, profile: Option<&String>) {
     log!(INFO, "Records were viewed");
 }
 
-fn store_new_credential(entry: String, profile: Option<&String>) {
-    let mut data: Vec<String> = Vec::new();
-    let path = PASSWORDFILE.get().unwrap();
-
-    let password: String = rpassword::prompt_password("[+] Enter master password: ").unwrap();
+/* get --field: one value of one record and nothing else, so it can be piped */
+fn print_record_field(query: Query, pick: argparse::Pick, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
 
-    let mut records: Vec<vault::Record> = match vault::load(path, &password, profile) {
-        Ok(y) => match y {
-            Some(x) => x,
-            None => Vec::new(),
-        },
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
         Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            } else {
-                println!("[!] Error: {err}");
-            }
+            report_load_error(err);
             return;
         }
     };
 
-    print!("[+] Enter username for '{}': ", entry);
-    data.push(vault::fgets());
-
-    print!("[+] Enter password for '{}' (default length 30) : ", entry);
-    let mut pass: String = vault::fgets();
-    if pass.is_empty() {
-        pass = vault::generate_rand_password(30);
-        println!("Generated password -> {}", pass);
-    }
-    data.push(pass);
-
-    print!("[+] Enter email for '{}' (optional): ", entry);
-    data.push(vault::fgets());
-
-    print!("[+] Enter note for '{}' (optional): ", entry);
-    data.push(vault::fgets());
-
-    data.insert(0, entry);
-    records.push(vault::Record::new(&data, &password));
-
-    if let Err(err) = vault::dump(&records, path, &password, profile) {
-        eprintln!("[!] Error: {err}");
+    let found = find_records(&records, &query);
+    if found.is_empty() {
+        fail!(NotFound, "Record with {} doesn't exists", describe_query(&query));
         return;
     }
 
-    println!("[+] Credentials was stored into the database!");
-    log!(INFO, "New record was added to the database");
-}
+    // a script can't pick, more than one match is an error
+    let idx = match single_record(&records, &found, &query) {
+        Some(x) => x,
+        None => return,
+    };
 
-fn update_existing_credential(search: String, profile: Option<&String>) {
-    let password: String = rpassword::prompt_password("[+] Enter master password: ").unwrap();
+    let value = match pick.field.to_lowercase().as_str() {
+        "otp" => {
+            let otp = match records[idx].otp() {
+                Some(x) => x.clone(),
+                None => {
+                    fail!(NotFound, "'{}' has no otp secret", records[idx].entry());
+                    return;
+                }
+            };
 
-    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
-        Ok(y) => match y {
+            let code = match otp.kind {
+                otp::OtpKind::Totp => otp.totp().map(|(code, _)| code),
+                otp::OtpKind::Hotp => records[idx].next_hotp(),
+            };
+            let code = match code {
+                Ok(x) => x,
+                Err(err) => {
+                    fail!(Failure, "Error: {err}");
+                    return;
+                }
+            };
+
+            // same as otp, the used counter is saved before the code is shown
+            if otp.kind == otp::OtpKind::Hotp
+                && let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+            code
+        },
+        _ => match records[idx].field_value(&pick.field) {
             Some(x) => x,
             None => {
-                println!("[!] No records were found!\nTry 'rustsafe --add' to create a new record");
+                fail!(NotFound, "'{}' has no {}", records[idx].entry(), pick.field);
                 return;
             }
         },
-        Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            } else {
-                println!("[!] Error: {err}");
-            }
-            return;
-        }
     };
 
-    let mut req_record: Option<(usize, &vault::Record)> = None;
+    if pick.copy {
+        return copy_to_clipboard(&records[idx], &pick.field, &value, &query);
+    }
 
-    for (idx, record) in records.iter().enumerate() {
-        if record.username().to_lowercase().contains(&search) || record.entry().to_lowercase().contains(&search) {
-            req_record = Some((idx, record));
-            break;
-        }
+    match output::format() {
+        output::Format::Json => output::json(serde_json::json!({
+            "entry": records[idx].entry(), "field": pick.field, "value": value
+        })),
+        _ if pick.newline => println!("{}", value),
+        _ => {
+            print!("{}", value);
+            let _ = std::io::stdout().flush();
+        },
+    }
 
-        if let Some(_email) = record.email() {
-            if _email.to_lowercase().contains(&search) {
-                req_record = Some((idx, record));
-                break;
-            }
+    log!(INFO, format!("The {} of a record was viewed with {}", pick.field, describe_query(&query)));
+}
+
+/* get --copy: the value goes to the clipboard and is taken off it again after clipboard_timeout */
+fn copy_to_clipboard(record: &vault::Record, field: &str, value: &str, query: &Query) {
+    let backend = match clipboard::copy(value) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            log!(ERROR, err);
+            return;
+        }
+    };
+
+    let timeout = config::get().clipboard_timeout;
+    if timeout == 0 {
+        say!("[+] The {} of '{}' was copied to the clipboard", field, record.entry());
+    } else {
+        match clipboard::schedule_clear(backend, value, timeout) {
+            Ok(()) if backend == clipboard::Backend::Osc52 => say!(
+                "[+] The {} of '{}' was copied to the clipboard, it is cleared in {}s even if something else was copied since",
+                field, record.entry(), timeout
+            ),
+            Ok(()) => say!("[+] The {} of '{}' was copied to the clipboard, it is cleared in {}s", field, record.entry(), timeout),
+            Err(err) => warn!("The {} of '{}' was copied but won't be cleared: {}", field, record.entry(), err),
+        }
+    }
+
+    log!(INFO, format!("The {} of a record was copied to the clipboard ({}) with {}", field, backend.name(), describe_query(query)));
+}
+
+/* Reminder under --list, --get and --lookup tables, scripts (json, plain, get --field, exports) don't get it */
+fn overdue_warning(records: &[vault::Record], password: &str, profile: Option<&String>) {
+    if output::format() != output::Format::Table {
+        return;
+    }
+    if let Ok(policy) = vault::profile_rotation(PASSWORDFILE.get().unwrap(), password, profile) {
+        rotation::warn_overdue(records, policy);
+    }
+}
+
+/* --list --expired and --due <days>: the matching logins by deadline, then when each one is due */
+fn display_due_records(records: Vec<vault::Record>, query: Query, days: u32, password: &str, profile: Option<&String>) {
+    let policy = match vault::profile_rotation(PASSWORDFILE.get().unwrap(), password, profile) {
+        Ok(x) => x,
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let matching = find_records(&records, &query);
+    let due: Vec<&vault::Record> = rotation::due(&records, policy, days).into_iter()
+        .filter(|x| matching.contains(x))
+        .map(|x| &records[x])
+        .collect();
+
+    if due.is_empty() {
+        match days {
+            0 => say!("[+] No passwords are past their rotation deadline"),
+            x => say!("[+] No passwords are due for rotation within {} day(s)", x),
+        }
+        return;
+    }
+
+    vault::record_fmt(vault::RecordPrint::VECTOR(due.iter().map(|x| (*x).clone()).collect()));
+    for record in &due {
+        say!("[#] {} ({}): {}, every {} days",
+            record.entry(), record.username(), rotation::describe(record, policy), rotation::policy(record, policy).unwrap_or_default());
+    }
+
+    log!(INFO, "Records due for rotation were viewed");
+}
+
+fn set_rotation_policy(policy: argparse::RotationPolicy, query: Query, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    /* no name: the profile's policy */
+    if query.text.is_empty() && query.id.is_none() {
+        let (days, message) = match policy {
+            argparse::RotationPolicy::Days(x) => (Some(x), format!("Logins of this profile now need a new password every {} days", x)),
+            argparse::RotationPolicy::Off | argparse::RotationPolicy::Default => (None, "The profile has no rotation policy anymore".to_string()),
+        };
+
+        if let Err(err) = vault::set_profile_rotation(PASSWORDFILE.get().unwrap(), &password, profile, days) {
+            report_load_error(err);
+            return;
+        }
+
+        say!("[+] {}", message);
+        log!(INFO, message);
+        return;
+    }
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    if records[idx].kind() != kinds::RecordKind::Login {
+        fail!(Input, "Error: '{}' is a {}, only logins have a rotation policy", records[idx].entry(), records[idx].kind().name());
+        return;
+    }
+
+    let (days, message) = match policy {
+        argparse::RotationPolicy::Days(x) => (Some(x), format!("'{}' now needs a new password every {} days", records[idx].entry(), x)),
+        argparse::RotationPolicy::Off => (Some(0), format!("'{}' is exempt from password rotation", records[idx].entry())),
+        argparse::RotationPolicy::Default => (None, format!("'{}' follows the profile's rotation policy", records[idx].entry())),
+    };
+    records[idx].set_rotation(days);
+
+    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] {}", message);
+    log!(INFO, message);
+}
+
+fn store_new_credential(entry: String, labels: argparse::Labels, profile: Option<&String>) {
+    let mut data: Vec<String> = Vec::new();
+    let path = PASSWORDFILE.get().unwrap();
+
+    // catch a bad --field or --url before anything is typed
+    let mut check = vault::Record::new(std::slice::from_ref(&entry));
+    check.set_kind(labels.kind, BTreeMap::new());
+    if let Some(err) = labels.fields.iter().find_map(|x| check.apply_field(x).err())
+        .or(labels.urls.iter().find_map(|x| urls::UrlRule::parse(x).and_then(|x| check.add_url(x)).err())) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    if labels.kind != kinds::RecordKind::Login && labels.has_login_values() {
+        fail!(Input, "Error: --username, --password-stdin, --generate-password, --email and --note only apply to logins");
+        return;
+    }
+
+    // the values of other kinds are only ever asked for, a script would be stuck answering prompts
+    if labels.kind != kinds::RecordKind::Login && !std::io::stdin().is_terminal() {
+        fail!(Input, "Error: a {} can only be added from a terminal, its values are prompted for", labels.kind.name());
+        return;
+    }
+
+    // before the master password, a script without a password on stdin fails before anything is unlocked
+    let secret = match resolve_password(labels.password) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(path, &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
         }
+    };
+
+    /* other kinds have their own prompts instead of username, password, email and note */
+    if labels.kind != kinds::RecordKind::Login {
+        let details = match prompt_details(labels.kind, &entry, None) {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut record = vault::Record::new(std::slice::from_ref(&entry));
+        record.set_kind(labels.kind, details);
+        return save_new_record(record, labels, records, &password, profile);
+    }
+
+    /* values given as flags (or --yes) skip every prompt */
+    if labels.has_login_values() || labels.yes {
+        let secret = secret.unwrap_or_else(|| {
+            say!("[#] No password was given, a random one of 30 characters was generated");
+            vault::generate_rand_password(30)
+        });
+
+        let data = vec![
+            entry,
+            labels.username.clone().unwrap_or_default(),
+            secret,
+            labels.email.clone().unwrap_or_default(),
+            labels.note.clone().unwrap_or_default(),
+        ];
+        return save_new_record(vault::Record::new(&data), labels, records, &password, profile);
+    }
+
+    ask!("[+] Enter username for '{}': ", entry);
+    data.push(vault::fgets());
+
+    ask!("[+] Enter password for '{}' (default length 30) : ", entry);
+    let mut pass: String = vault::fgets();
+    if pass.is_empty() {
+        pass = vault::generate_rand_password(30);
+        say!("Generated password -> {}", pass);
+    }
+    data.push(pass);
+
+    ask!("[+] Enter email for '{}' (optional): ", entry);
+    data.push(vault::fgets());
+
+    ask!("[+] Enter note for '{}' (optional): ", entry);
+    data.push(vault::fgets());
+
+    data.insert(0, entry);
+    save_new_record(vault::Record::new(&data), labels, records, &password, profile);
+}
+
+fn save_new_record(mut record: vault::Record, labels: argparse::Labels, mut records: Vec<vault::Record>, password: &str, profile: Option<&String>) {
+    let path = PASSWORDFILE.get().unwrap();
+
+    for tag in &labels.tags {
+        record.add_tag(tag);
+    }
+    if let Some(folder) = &labels.folder {
+        record.set_folder(folder);
+    }
+    for field in &labels.fields {
+        let _ = record.apply_field(field);      // checked above
+    }
+    for url in &labels.urls {
+        let _ = urls::UrlRule::parse(url).and_then(|x| record.add_url(x));
+    }
+
+    records.push(record);
+
+    if let Err(err) = vault::dump(&records, path, password, profile) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] Credentials was stored into the database!");
+    log!(INFO, "New record was added to the database");
+}
+
+/* Asks for every value of a kind's schema, with `old` an empty answer keeps the current value */
+fn prompt_details(kind: kinds::RecordKind, entry: &str, old: Option<&BTreeMap<String, String>>) -> Option<BTreeMap<String, String>> {
+    let mut details = BTreeMap::new();
+
+    for field in kind.schema() {
+        let current = old.and_then(|x| x.get(field.name));
+        let hint = match current {
+            Some(_) => " (empty keeps it)",
+            None if !field.required => " (optional)",
+            None => "",
+        };
+
+        let mut tries = 0;
+        loop {
+            // a closed stdin would otherwise ask forever
+            tries += 1;
+            if tries > 3 {
+                fail!(Input, "No valid {} was given, nothing was saved", field.label);
+                return None;
+            }
+
+            let value = match field.input {
+                kinds::Input::Line => {
+                    ask!("[+] Enter {} for '{}'{}: ", field.label, entry, hint);
+                    vault::fgets()
+                },
+                kinds::Input::Hidden => rpassword::prompt_password(
+                    format!("[+] Enter {} for '{}'{}: ", field.label, entry, hint)
+                ).unwrap_or_default(),
+                kinds::Input::Lines => {
+                    say!("[+] Enter {} for '{}'{}, end with an empty line:", field.label, entry, hint);
+                    let mut lines = Vec::new();
+                    loop {
+                        let line = vault::fgets();
+                        if line.is_empty() { break; }
+                        lines.push(line);
+                    }
+                    lines.join("\n")
+                },
+                kinds::Input::File => {
+                    ask!("[+] Enter the path of the {} for '{}'{}: ", field.label, entry, hint);
+                    match vault::fgets() {
+                        x if x.is_empty() => x,
+                        path => match fs::read_to_string(&path) {
+                            Ok(x) => x.trim_end().to_string(),
+                            Err(err) => {
+                                warn!("Error reading {}: {err}", path);
+                                continue;
+                            }
+                        },
+                    }
+                },
+            };
 
-        if let Some(_note) = record.note() {
-            if _note.to_lowercase().contains(&search) {
-                req_record = Some((idx, record));
+            if value.is_empty() {
+                match current {
+                    Some(x) => { details.insert(field.name.to_string(), x.clone()); },
+                    None if field.required => {
+                        warn!("The {} is required", field.label);
+                        continue;
+                    },
+                    None => {},
+                }
                 break;
             }
+
+            match kinds::validate(field.name, &value) {
+                Ok(x) => {
+                    details.insert(field.name.to_string(), x);
+                    break;
+                },
+                Err(err) => warn!("Error: {err}"),
+            }
+        }
+    }
+
+    Some(details)
+}
+
+fn update_existing_credential(query: Query, labels: argparse::Labels, profile: Option<&String>) {
+    let secret = match resolve_password(labels.password) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => match y {
+            Some(x) => x,
+            None => {
+                fail!(NotFound, "No records were found!\nTry 'rustsafe --add' to create a new record");
+                return;
+            }
+        },
+        Err(err) => {
+            report_load_error(err);
+            return;
         }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        log!(INFO, format!("Password updation failed no password's were found with {}", describe_query(&query)));
+        return;
     }
 
-    if let None = req_record {
-        println!("[!] No Records were found with that phrase '{}'", search);
-        log!(INFO, format!("Password updation failed no password's were found with the phrase '{}'", search));
+    /* fields, urls and login values given as flags (or --yes) skip the prompts */
+    let unattended = !labels.fields.is_empty() || !labels.urls.is_empty() || !labels.remove_urls.is_empty()
+        || labels.has_login_values() || labels.yes;
+
+    let idx = match unattended {
+        true => single_record(&records, &found, &query),
+        false => pick_record(&records, &found),
+    };
+    let idx = match idx {
+        Some(x) => x,
+        None => return,
+    };
+
+    let record = records[idx].clone();
+
+    if unattended {
+        if labels.has_login_values() {
+            if record.kind() != kinds::RecordKind::Login {
+                fail!(Input, "Error: '{}' is a {}, --username, --password-stdin, --generate-password, --email and --note only apply to logins",
+                    record.entry(), record.kind().name());
+                return;
+            }
+
+            let data = vec![
+                record.entry(),
+                labels.username.clone().unwrap_or(record.username()),
+                secret.unwrap_or(record.password()),
+                labels.email.clone().or(record.email()).unwrap_or_default(),
+                labels.note.clone().or(record.note()).unwrap_or_default(),
+            ];
+            records[idx] = record.updated(&data);
+        }
+
+        for field in &labels.fields {
+            if let Err(err) = records[idx].apply_field(field) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+        }
+
+        for url in &labels.urls {
+            if let Err(err) = urls::UrlRule::parse(url).and_then(|x| records[idx].add_url(x)) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+        }
+
+        for url in &labels.remove_urls {
+            if !records[idx].remove_url(url) {
+                fail!(Input, "Error: '{}' has no url '{}'", records[idx].entry(), url);
+                return;
+            }
+        }
+
+        if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+
+        say!("[+] Credentials was updated sucessfully");
+        log!(INFO, format!("Credentials was updated without prompts with {}", describe_query(&query)));
         return;
     }
 
-    let (idx, record) = req_record.unwrap();
     vault::record_fmt(vault::RecordPrint::RECORD(record.clone()));
 
-    print!("[+] Do you want to change this record ? (Y/n) : ");
+    ask!("[+] Do you want to change this record ? (Y/n) : ");
     let choice = vault::fgets().to_lowercase();
 
     if choice.is_empty() || choice.starts_with('n') {
-        println!("[#] Record Wasnt Updated!");
+        say!("[#] Record Wasnt Updated!");
+        return;
+    }
+
+    if record.kind() != kinds::RecordKind::Login {
+        let details = match prompt_details(record.kind(), &record.entry(), Some(record.details())) {
+            Some(x) => x,
+            None => return,
+        };
+
+        records[idx].set_kind(record.kind(), details);
+
+        if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+
+        say!("[+] Credentials was updated sucessfully");
+        log!(INFO, format!("Credentials was updated with {}", describe_query(&query)));
         return;
     }
 
     {
         let mut data: Vec<String> = Vec::new();
-        print!("[+] Enter new username for '{}' (optional): ", (*record).entry());
-        data.push((*record).entry());
+        ask!("[+] Enter new username for '{}' (optional): ", record.entry());
+        data.push(record.entry());
 
         let _u = vault::fgets();
-        if _u.is_empty() { data.push((*record).username()) } else { data.push(_u) }
+        if _u.is_empty() { data.push(record.username()) } else { data.push(_u) }
 
-        print!("[+] Enter new password for '{}' (optional): ", (*record).entry());
+        ask!("[+] Enter new password for '{}' (optional): ", record.entry());
         let _p = vault::fgets();
-        if _p.is_empty() { data.push((*record).password()) } else { data.push(_p) }
+        if _p.is_empty() { data.push(record.password()) } else { data.push(_p) }
 
-        print!("[+] Enter new email for '{}' (optional): ", (*record).entry());
+        ask!("[+] Enter new email for '{}' (optional): ", record.entry());
         let _e = vault::fgets();
         if _e.is_empty() {
-            if let Some(_email) = (*record).email() {
+            if let Some(_email) = record.email() {
                 data.push(_email);
             } else {
                 data.push(_e);              // just send the "" new function will convert it to None
@@ -498,10 +1352,10 @@ fn update_existing_credential(search: String, profile: Option<&String>) {
             data.push(_e);
         }
 
-        print!("[+] Enter new note for '{}' (optional): ", (*record).entry());
+        ask!("[+] Enter new note for '{}' (optional): ", record.entry());
         let _n = vault::fgets();
         if _n.is_empty() {
-            if let Some(_note) = (*record).note() {
+            if let Some(_note) = record.note() {
                 data.push(_note);
             } else {
                 data.push(_n);               // just send the "" new function will convert it to None
@@ -510,275 +1364,898 @@ fn update_existing_credential(search: String, profile: Option<&String>) {
             data.push(_n);
         }
 
-        records[idx] = vault::Record::new(&data, &password);
+        records[idx] = record.updated(&data);
 
-        println!("[+] Credentials was updated sucessfully");
+        say!("[+] Credentials was updated sucessfully");
 
         if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
-            eprintln!("[!] Error: {err}");
+            fail!(Failure, "Error: {err}");
             return;
         }
 
-        log!(INFO, format!("Credentials was updated with the phrase '{}'", search));
+        log!(INFO, format!("Credentials was updated with {}", describe_query(&query)));
     }
 }
 
-fn update_master_password(profile: Option<&String>) {
-    let password: String = rpassword::prompt_password("[+] Enter master password: ").unwrap();
+fn lookup_url(url: String, profile: Option<&String>) {
+    let target = match urls::normalize(&url) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
 
     let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
-        Ok(y) => match y {
-            Some(x) => x,
-            None => {
-                println!("[!] No records were found!\nTry 'rustsafe --add' to create a new record");
-                return;
-            }
-        },
+        Ok(y) => y.unwrap_or_default(),
         Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            } else {
-                println!("[!] Error: {err}");
-            }
+            report_load_error(err);
             return;
         }
     };
 
-    let passwd: String = rpassword::prompt_password("[+] Enter new master password: ").unwrap();
-    let _password: String = rpassword::prompt_password("[+] Enter new master password again: ").unwrap();
+    /* most specific rule first: prefix, host, regex, then domain */
+    overdue_warning(&records, &password, profile);
+    let mut found: Vec<(urls::MatchRule, vault::Record)> = records.into_iter()
+        .filter_map(|record| {
+            let rule = record.urls().iter().filter(|x| x.matches(&target)).map(|x| x.rule).min()?;
+            Some((rule, record))
+        })
+        .collect();
+    found.sort_by_key(|(rule, _)| *rule);
 
-    if passwd != _password {
-        println!("[!] Passwords doesn't match!");
-        log!(INFO, "Master Password change failed, Passwords doesnt match");
+    if found.is_empty() {
+        fail!(NotFound, "No logins match '{}'", target);
+        log!(INFO, "A url lookup found nothing");
         return;
     }
 
-    let mut new_records: Vec<vault::Record> = Vec::new();
-    for record in records {
-        let mut data = vec![record.entry(), record.username(), record.password()];
+    vault::record_fmt(vault::RecordPrint::VECTOR(found.into_iter().map(|(_, x)| x).collect()));
+    log!(INFO, "Records were looked up by url");
+}
 
-        if let Some(email) = record.email() {
-            data.push(email);
-        } else {
-            data.push("".to_owned());
+fn display_folder_tree(query: Query, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
         }
+    };
 
-        if let Some(note) = record.note() {
-            data.push(note);
-        } else {
-            data.push("".to_owned());
+    let found: Vec<vault::Record> = find_records(&records, &query)
+        .into_iter()
+        .map(|idx| records[idx].clone())
+        .collect();
+
+    vault::folder_tree(&found);
+    log!(INFO, "Folder tree was viewed");
+}
+
+/* --tag add|rm and --move on one picked record */
+fn update_record_labels(query: Query, tag: Option<argparse::TagAction>, folder: Option<String>, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let record = &mut records[idx];
+    let (changed, message) = match (tag, folder) {
+        (Some(argparse::TagAction::Add(tag)), _) => match record.add_tag(&tag) {
+            true => (true, format!("Tag '{}' was added to '{}'", tag, record.entry())),
+            false => (false, format!("'{}' is already tagged '{}'", record.entry(), tag)),
+        },
+        (Some(argparse::TagAction::Remove(tag)), _) => match record.remove_tag(&tag) {
+            true => (true, format!("Tag '{}' was removed from '{}'", tag, record.entry())),
+            false => (false, format!("'{}' isn't tagged '{}'", record.entry(), tag)),
+        },
+        (None, Some(folder)) => {
+            record.set_folder(&folder);
+            (true, format!("'{}' was moved to /{}", record.entry(), record.folder().unwrap_or_default()))
+        },
+        (None, None) => return,
+    };
+
+    if !changed {
+        say!("[#] {}", message);
+        return;
+    }
+
+    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] {}", message);
+    log!(INFO, message);
+}
+
+fn password_history(query: Query, restore: Option<usize>, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    if let Some(n) = restore {
+        if let Err(err) = records[idx].restore_password(n) {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+
+        if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+
+        say!("[+] Password #{} of '{}' was restored, the replaced one is now #1 in its history", n, records[idx].entry());
+        log!(INFO, format!("A previous password was restored with {}", describe_query(&query)));
+        return;
+    }
+
+    let record = &records[idx];
+    let time = |x: Option<chrono::DateTime<chrono::Utc>>| match x {
+        Some(x) => x.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "unknown".to_string(),
+    };
+
+    let reveal = output::reveals("password");
+    if output::format() == output::Format::Json {
+        let history: Vec<serde_json::Value> = record.history().iter()
+            .enumerate()
+            .map(|(n, old)| serde_json::json!({
+                "n": n + 1,
+                "password": if reveal { old.password.as_str() } else { output::MASK },
+                "replaced_at": old.replaced_at,
+            }))
+            .collect();
+
+        output::json(serde_json::json!({
+            "id": record.id(), "entry": record.entry(), "username": record.username(),
+            "created_at": record.created_at(), "updated_at": record.updated_at(),
+            "password_changed_at": record.password_changed_at(), "history": history,
+        }));
+        log!(INFO, format!("Password history was viewed with {}", describe_query(&query)));
+        return;
+    }
+
+    say!("[#] {} ({}) {}", record.entry(), record.username(), record.id());
+    say!("  created:          {}", time(record.created_at()));
+    say!("  updated:          {}", time(record.updated_at()));
+    say!("  password changed: {}", time(record.password_changed_at()));
+
+    if record.history().is_empty() {
+        say!("[#] No previous passwords are kept for this record");
+    } else {
+        say!("[#] Previous passwords (restore with --restore <n>):");
+        for (n, old) in record.history().iter().enumerate() {
+            let shown = if reveal { old.password.as_str() } else { output::MASK };
+            say!("  {}) {}  replaced {}", n + 1, shown, time(old.replaced_at));
+        }
+    }
+
+    log!(INFO, format!("Password history was viewed with {}", describe_query(&query)));
+}
+
+fn show_otp_code(query: Query, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    // records without a secret can't be picked
+    let found: Vec<usize> = find_records(&records, &query).into_iter().filter(|x| records[*x].otp().is_some()).collect();
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records with an otp secret were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let otp = records[idx].otp().cloned().unwrap();
+    match otp.kind {
+        otp::OtpKind::Totp => match otp.totp() {
+            Ok((code, remaining)) => match output::format() {
+                output::Format::Table => println!("[$] {} -> {} (valid for {}s)", records[idx].entry(), code, remaining),
+                output::Format::Plain => println!("{}", code),
+                output::Format::Json => output::json(serde_json::json!({
+                    "entry": records[idx].entry(), "code": code, "valid_for": remaining
+                })),
+            },
+            Err(err) => {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+        },
+        otp::OtpKind::Hotp => {
+            let code = match records[idx].next_hotp() {
+                Ok(x) => x,
+                Err(err) => {
+                    fail!(Failure, "Error: {err}");
+                    return;
+                }
+            };
+
+            // the counter has to be saved before the code is shown, or it could be handed out twice
+            if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+            match output::format() {
+                output::Format::Table => println!("[$] {} -> {} (counter {})", records[idx].entry(), code, otp.counter),
+                output::Format::Plain => println!("{}", code),
+                output::Format::Json => output::json(serde_json::json!({
+                    "entry": records[idx].entry(), "code": code, "counter": otp.counter
+                })),
+            }
+        },
+    }
+
+    log!(INFO, format!("An otp code was generated with {}", describe_query(&query)));
+}
+
+fn set_otp_secret(query: Query, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    // read hidden, the secret is as sensitive as the password
+    let secret = match rpassword::prompt_password("[+] Enter the otpauth:// uri or base32 secret (empty removes it): ") {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let message = if secret.trim().is_empty() {
+        if records[idx].otp().is_none() {
+            say!("[#] '{}' has no otp secret", records[idx].entry());
+            return;
+        }
+        records[idx].set_otp(None);
+        format!("The otp secret of '{}' was removed", records[idx].entry())
+    } else {
+        match otp::OtpConfig::parse(&secret) {
+            Ok(x) => {
+                let summary = x.summary();
+                records[idx].set_otp(Some(x));
+                format!("The otp secret of '{}' was saved ({})", records[idx].entry(), summary)
+            },
+            Err(err) => {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+        }
+    };
+
+    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] {}", message);
+    log!(INFO, message);
+}
+
+fn attach_file(query: Query, file: String, profile: Option<&String>) {
+    let data = match fs::read(&file) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error reading {}: {err}", file);
+            return;
+        }
+    };
+
+    let name = match std::path::Path::new(&file).file_name() {
+        Some(x) => x.to_string_lossy().to_string(),
+        None => {
+            fail!(Input, "Error: '{}' is not a file", file);
+            return;
+        }
+    };
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    // the blob is written first, if the vault can't be saved afterwards the sweep removes it again
+    let (id, key) = match blobs::store(PASSWORDFILE.get().unwrap(), &data) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let replaced = records[idx].attach(vault::Attachment {
+        name: name.clone(), id, key, size: data.len() as u64, added_at: Some(chrono::Utc::now()),
+    });
+
+    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    let message = format!(
+        "'{}' was {} '{}' ({} bytes)", name, if replaced { "replaced on" } else { "attached to" }, records[idx].entry(), data.len()
+    );
+    say!("[+] {}", message);
+    log!(INFO, message);
+}
+
+fn list_attachments(query: Query, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let found = find_records(&records, &query);
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let record = &records[idx];
+    if output::format() == output::Format::Json {
+        let attachments: Vec<serde_json::Value> = record.attachments().iter()
+            .map(|x| serde_json::json!({ "name": x.name, "size": x.size, "added_at": x.added_at }))
+            .collect();
+        return output::json(serde_json::json!({ "id": record.id(), "entry": record.entry(), "attachments": attachments }));
+    }
+
+    if record.attachments().is_empty() {
+        say!("[#] '{}' has no attachments. Add one with --attach <name> <file>", record.entry());
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct AttachmentRow {
+        name: String,
+        size: String,
+        added: String,
+    }
+
+    let rows: Vec<AttachmentRow> = record.attachments().iter().map(|x| AttachmentRow {
+        name: x.name.clone(),
+        size: format!("{} bytes", x.size),
+        added: match x.added_at {
+            Some(time) => time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "unknown".to_string(),
+        },
+    }).collect();
+
+    let mut table = Table::new(rows);
+    table.with(Style::rounded());
+    say!("[#] Attachments of '{}':", record.entry());
+    say!("{table}");
+}
+
+fn extract_attachment(query: Query, name: String, out: Option<String>, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    // only records that have this attachment can be picked
+    let found: Vec<usize> = find_records(&records, &query).into_iter()
+        .filter(|x| records[*x].attachments().iter().any(|a| a.name == name))
+        .collect();
+
+    if found.is_empty() {
+        fail!(NotFound, "No Records with an attachment '{}' were found with {}", name, describe_query(&query));
+        return;
+    }
+
+    let idx = match pick_record(&records, &found) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let attachment = records[idx].attachments().iter().find(|x| x.name == name).unwrap();
+    let data = match blobs::load(PASSWORDFILE.get().unwrap(), &attachment.id, &attachment.key) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            log!(ERROR, err);
+            return;
+        }
+    };
+
+    // names come from file_name() but an imported record could carry anything, stay in the current directory
+    let out = out.unwrap_or_else(|| match std::path::Path::new(&attachment.name).file_name() {
+        Some(x) => x.to_string_lossy().to_string(),
+        None => "attachment".to_string(),
+    });
+    if let Err(err) = blobs::write_out(&out, &data) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    let message = format!("'{}' of '{}' was extracted to '{}'", attachment.name, records[idx].entry(), out);
+    say!("[+] {}", message);
+    log!(INFO, message);
+}
+
+fn update_master_password(profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => match y {
+            Some(x) => x,
+            None => {
+                fail!(NotFound, "No records were found!\nTry 'rustsafe --add' to create a new record");
+                return;
+            }
+        },
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let passwd = match prompt_new_password() {
+        Some(x) => x,
+        None => {
+            log!(INFO, "Master Password change failed, Passwords doesnt match");
+            return;
+        }
+    };
+
+    if let Err(err) = vault::rekey(&records, PASSWORDFILE.get().unwrap(), &password, &passwd, profile, None) {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] Master password was changed successfully!");
+    log!(INFO, "Master password was changed");
+}
+
+fn tune_key_derivation(target: u128, profile: Option<&String>) {
+    say!("[+] Benchmarking argon2id for a {} ms unlock, this can take a while...", target);
+
+    let (params, elapsed) = match kdf::tune(target) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            log!(ERROR, err);
+            return;
+        }
+    };
+
+    say!("[$] memory: {} KiB, iterations: {}, parallelism: {} -> {} ms",
+        params.memory, params.iterations, params.parallelism, elapsed);
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
         }
+    };
 
-        new_records.push(vault::Record::new(&data, &_password));
-    }
-
-    if let Err(err) = vault::dump(&new_records, PASSWORDFILE.get().unwrap(), &_password, profile) {
-        eprintln!("[!] Error: {err}");
+    if let Err(err) = vault::rekey(&records, PASSWORDFILE.get().unwrap(), &password, &password, profile, Some(params)) {
+        fail!(Failure, "Error: {err}");
+        return;
     }
 
-    println!("[+] Master password was changed successfully!");
-    log!(INFO, "Master password was changed");
+    say!("[+] Key derivation parameters were updated!");
+    log!(INFO, "Key derivation parameters were tuned");
 }
 
-fn remove_existing_credential(search: String, profile: Option<&String>) {
-    let password: String = rpassword::prompt_password("[+] Enter master password: ").unwrap();
+fn remove_existing_credential(query: Query, yes: bool, profile: Option<&String>) {
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
 
     let mut records: Vec<vault::Record> = match vault::load(PASSWORDFILE.get().unwrap(), &password, profile) {
         Ok(y) => match y {
             Some(x) => x,
             None => {
-                println!("[!] No records were found!\nTry 'rustsafe --add' to create a new record");
+                fail!(NotFound, "No records were found!\nTry 'rustsafe --add' to create a new record");
                 return;
             }
         },
         Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            } else {
-                println!("[!] Error: {err}");
-            }
+            report_load_error(err);
             return;
         }
     };
 
-    let mut req_records: Vec<(usize, &vault::Record)> = Vec::new();
+    let found = find_records(&records, &query);
 
-    for (idx, record) in records.iter().enumerate() {
-        if record.username().to_lowercase().contains(&search) || (*record).entry().to_lowercase().contains(&search) {
-            req_records.push((idx, record));
-            continue;
-        }
+    if found.is_empty() {
+        fail!(NotFound, "No Records were found with {}", describe_query(&query));
+        log!(INFO, format!("Password deletion failed no password's were found with {}", describe_query(&query)));
+        return;
+    }
 
-        if let Some(_email) = record.email() {
-            if _email.to_lowercase().contains(&search) {
-                req_records.push((idx, record));
-                continue;
-            }
-        }
+    let idx = match yes {
+        true => single_record(&records, &found, &query),
+        false => pick_record(&records, &found),
+    };
+    let idx = match idx {
+        Some(x) => x,
+        None => return,
+    };
 
-        if let Some(_note) = record.note() {
-            if _note.to_lowercase().contains(&search) {
-                req_records.push((idx, record));
-                continue;
-            }
+    if !yes {
+        vault::record_fmt(vault::RecordPrint::RECORD(records[idx].clone()));
+
+        ask!("[+] Do you want to delete this record ? (Y/n) : ");
+        let choice = vault::fgets().to_lowercase();
+
+        if !choice.starts_with('y') {
+            say!("[#] Record Wasnt Deleted!");
+            return;
         }
     }
 
-    if req_records.len() == 0 {
-        println!("[!] No Records were found with that phrase '{}'", search);
-        log!(INFO, format!("Password deletion failed no password's were found with the phrase '{}'", search));
-        return;
-    }
+    records.remove(idx);
+    say!("[+] Record was Deleted!");
+    log!(INFO, "Record was Deleted");
 
-    for (idx, record) in &req_records {
-        vault::record_fmt(vault::RecordPrint::RECORD((*record).clone()));
+    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
+        fail!(Failure, "Error: {err}");
+    }
+}
 
-        print!("[+] Do you want to delete this record ? (Y/n) : ");
-        let choice = vault::fgets().to_lowercase();
+/* Records from a --export file, json keeps everything, csv the columns it has */
+fn read_import_file(path: &str) -> Result<Vec<vault::Record>, String> {
+    if path.to_lowercase().ends_with(".json") {
+        let data = fs::read_to_string(path).map_err(|e| format!("{e}"))?;
+        let records: Vec<vault::Record> = serde_json::from_str(&data).map_err(|e| format!("Invalid json export: {e}"))?;
 
-        if choice.starts_with('y') {
-            records.remove(*idx);
-            println!("[+] Record was Deleted!");
-            log!(INFO, "Record was Deleted");
-            break;
+        // exports don't carry attachments, a file that does can't point rsafe at paths outside the blob directory
+        for attachment in records.iter().flat_map(|x| x.attachments()) {
+            blobs::check_id(&attachment.id)?;
         }
-
-        println!("[#] Record Wasnt Deleted!");
+        return Ok(records);
     }
 
-    if let Err(err) = vault::dump(&records, PASSWORDFILE.get().unwrap(), &password, profile) {
-        eprintln!("[!] Error: {err}");
-        return;
+    let mut reader = Reader::from_path(path).map_err(|e| format!("{e}"))?;
+
+    // exports from older versions only have the first five columns
+    let headers = reader.headers().map_err(|e| format!("{e}"))?.clone();
+    let column = |name: &str| headers.iter().position(|x| x.eq_ignore_ascii_case(name));
+    let (tags_column, folder_column, fields_column) = (column("tags"), column("folder"), column("fields"));
+    let otp_column = column("otp");
+    let (kind_column, details_column, urls_column) = (column("kind"), column("details"), column("urls"));
+
+    let mut records = Vec::new();
+    for read in reader.records() {
+        let rec: Vec<String> = read.map_err(|e| format!("{e}"))?.iter().map(|f| f.to_string()).collect();
+        let mut record = vault::Record::new(&rec[..rec.len().min(5)]);
+
+        if let Some(tags) = tags_column.and_then(|x| rec.get(x)) {
+            tags.split(';').for_each(|tag| { record.add_tag(tag); });
+        }
+        if let Some(folder) = folder_column.and_then(|x| rec.get(x)) {
+            record.set_folder(folder);
+        }
+        if let Some(fields) = fields_column.and_then(|x| rec.get(x)).filter(|x| !x.is_empty()) {
+            record.set_fields(serde_json::from_str(fields).map_err(|e| format!("Invalid Fields column: {e}"))?);
+        }
+        if let Some(kind) = kind_column.and_then(|x| rec.get(x)).filter(|x| !x.is_empty()) {
+            let details = match details_column.and_then(|x| rec.get(x)).filter(|x| !x.is_empty()) {
+                Some(x) => serde_json::from_str(x).map_err(|e| format!("Invalid Details column: {e}"))?,
+                None => BTreeMap::new(),
+            };
+            record.set_kind(kinds::RecordKind::parse(kind)?, details);
+        }
+        if let Some(urls) = urls_column.and_then(|x| rec.get(x)).filter(|x| !x.is_empty()) {
+            record.set_urls(serde_json::from_str(urls).map_err(|e| format!("Invalid Urls column: {e}"))?);
+        }
+        if let Some(uri) = otp_column.and_then(|x| rec.get(x)).filter(|x| !x.is_empty()) {
+            record.set_otp(Some(otp::OtpConfig::parse(uri).map_err(|e| format!("Invalid Otp column: {e}"))?));
+        }
+        records.push(record);
     }
+
+    Ok(records)
 }
 
 // Profile if --from is used
 fn import_credentials_from_json(path: String, profile: Option<&String>) {
-    let mut data = Vec::new();
-
-    {
-        let mut reader = Reader::from_path(&path).unwrap();
+    let mut new_records = match read_import_file(&path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
 
-        for read in reader.records() {
-            let read = read.unwrap();
-            data.push(read.iter().map(|f| f.to_string()).collect::<Vec<_>>());
+    let dump = match DumpFile::load_dumpfile(PASSWORDFILE.get().unwrap()) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
         }
-    }
+    };
+    let index_key = index_password(&dump);
 
-    let dump = DumpFile::load_dumpfile(PASSWORDFILE.get().unwrap()).unwrap();
     let profile = match profile {
-        Some(x) => {
-            if dump.profiles.contains_key(x) {
-                print!("[#] This Profile is available do you want to append the creadentials to this profile ? (Y/n)");
-                let choice = match vault::fgets().to_lowercase().chars().nth(0) {
+        Some(x) => match dump.contains_profile(x, index_key.as_deref()) {
+            Ok(true) => {
+                ask!("[#] This Profile is available do you want to append the creadentials to this profile ? (Y/n)");
+                let choice = match vault::fgets().to_lowercase().chars().next() {
                     Some(x) => x,
                     None => {
-                        eprintln!("[!] Error: Please enter something!");
+                        fail!(Input, "Error: Please enter something!");
                         return;
                     }
                 };
 
-                if choice == 'y' { profile } else { None }
-            } else {
+                if choice == 'y' { Some(x.clone()) } else { None }
+            },
+            Ok(false) => {
                 create_profile(x.to_string());
-                profile
+                Some(x.clone())
+            },
+            Err(err) => {
+                report_load_error(err);
+                return;
             }
         },
         None => {
             // default profile
-            print!("[#] Import passwords into default profile ? (Y/n)");
-            let choice = match vault::fgets().to_lowercase().chars().nth(0) {
+            ask!("[#] Import passwords into default profile ? (Y/n)");
+            let choice = match vault::fgets().to_lowercase().chars().next() {
                 Some(x) => x,
                 None => {
-                    eprintln!("[!] Error: Please enter something!");
+                    fail!(Input, "Error: Please enter something!");
                     return;
                 }
             };
 
-            if choice == 'y' { dump.default.as_ref() } else { None }
+            if choice != 'y' {
+                return;
+            }
+
+            match dump.default_profile(index_key.as_deref()) {
+                Ok(x) => x,
+                Err(err) => {
+                    report_load_error(err);
+                    return;
+                }
+            }
         }
     };
 
+    let profile = profile.as_ref();
+
     if profile.is_none() {
         return;
     }
 
-    let password: String = rpassword::prompt_password(
-        format!("[+] Enter master password for `{}` profile: ", profile.unwrap())
-    ).unwrap();
+    let password = match prompt_master_password(
+        format!("[+] Enter master password for `{}` profile: ", profile.unwrap()), profile
+    ) {
+        Some(x) => x,
+        None => return,
+    };
 
     let path = PASSWORDFILE.get().unwrap();
 
-    let new_records = data.iter()
-        .map(|rec| vault::Record::new(rec, &password))
-        .collect::<Vec<_>>();
-
     let mut records = match vault::load(path, &password, profile) {
-        Ok(y) => match y {
-            Some(x) => x,
-            None => {
-                Vec::new()
-            }
-        },
+        Ok(y) => y.unwrap_or_default(),
         Err(err) => {
-            if err.contains("[!] Error decrypting message") {
-                println!("[!] Incorrect Password");
-                log!(INVALID, "Incorrect Password");
-            }
+            report_load_error(err);
             return;
         }
     };
+
+    // importing the same json export twice must not produce two records with one id
+    for record in new_records.iter_mut() {
+        if records.iter().any(|x| x.id() == record.id()) {
+            record.renew_id();
+        }
+    }
     records.extend(new_records);
 
-    if let Err(_) = vault::dump(&records, path, &password, profile) {
-        eprintln!("[!] Error: while writting password to password file!");
+    if vault::dump(&records, path, &password, profile).is_err() {
+        fail!(Failure, "Error: while writting password to password file!");
         return;
     }
 
     let query = format!("Passwords were imported successfully from {} into profile '{}'", path, profile.unwrap());
-    println!("[+] {}", query);
+    say!("[+] {}", query);
     log!(INFO, query);
 }
 
-fn export_credentials_to_json() {
+fn export_credentials_to_json(json: bool, otp_secrets: bool) {
     let path = PASSWORDFILE.get().unwrap();
 
-    let profiles = match DumpFile::load_dumpfile(&path) {
-        Ok(x) => x.profiles,
+    let dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let profiles = match dump.profile_names(index_password(&dump).as_deref()) {
+        Ok(x) => x,
         Err(err) => {
-            eprintln!("Error: {err}");
+            report_load_error(err);
             return;
         }
     };
 
-    for (profile_name, _) in &profiles {
-        let password: String = rpassword::prompt_password(
-            format!("[+] Enter master password for `{}` profile: ", profile_name)
-        ).unwrap();
+    for profile_name in &profiles {
+        let password = match prompt_master_password(
+            format!("[+] Enter master password for `{}` profile: ", profile_name), Some(profile_name)
+        ) {
+            Some(x) => x,
+            None => return,
+        };
 
         let records = match vault::load(path, &password, Some(profile_name)) {
             Ok(y) => match y {
                 Some(x) => x,
                 None => {
-                    println!("[!] No records were found to export!\nTry 'rustsafe --add' to create a new record");
+                    fail!(NotFound, "No records were found to export!\nTry 'rustsafe --add' to create a new record");
                     return;
                 }
             },
             Err(err) => {
-                if err.contains("[!] Error decrypting message") {
-                    println!("[!] Incorrect Password");
-                    log!(INVALID, "Incorrect Password");
-                }
+                report_load_error(err);
                 return;
             }
         };
 
+        if json {
+            let export_file_name = format!("{}/exportfile_{}.json", EXPORTFILE.get().unwrap(), profile_name);
+            let records: Vec<vault::Record> = records.iter().map(|x| x.exported(otp_secrets)).collect();
+            let data = serde_json::to_string_pretty(&records).unwrap();
+
+            if let Err(err) = fs::write(&export_file_name, data) {
+                fail!(Failure, "Error: {err}");
+                return;
+            }
+
+            say!("[+] Record was exported to '{}'", export_file_name);
+            continue;
+        }
+
         let export_file_name = format!("{}/exportfile_{}.csv", EXPORTFILE.get().unwrap(), profile_name);
 
         let mut writer = Writer::from_path(&export_file_name).unwrap();
-        writer.write_record(&["Entry", "Username", "Password", "Email", "Note"]).unwrap();
+        writer.write_record(["Entry", "Username", "Password", "Email", "Note", "Tags", "Folder", "Fields", "Otp", "Kind", "Details", "Urls"]).unwrap();
 
         for record in records {
             writer.write_record(&[
@@ -787,12 +2264,353 @@ fn export_credentials_to_json() {
                 record.password(),
                 record.email().unwrap_or_else(|| "".into()),
                 record.note().unwrap_or_else(|| "".into()),
+                record.tags().join(";"),
+                record.folder().unwrap_or_default(),
+                if record.fields().is_empty() { String::new() } else { serde_json::to_string(record.fields()).unwrap() },
+                record.otp().filter(|_| otp_secrets).map(|x| x.to_uri()).unwrap_or_default(),
+                record.kind().name().to_string(),
+                if record.details().is_empty() { String::new() } else { serde_json::to_string(record.details()).unwrap() },
+                if record.urls().is_empty() { String::new() } else { serde_json::to_string(record.urls()).unwrap() },
             ]).unwrap();
         }
 
-        println!("[+] Record was exported to '{}'", export_file_name);
+        say!("[+] Record was exported to '{}'", export_file_name);
 
     }
 
     log!(INFO, format!("Record was exported to '{}'", EXPORTFILE.get().unwrap()));
 }
+
+fn seal_metadata() {
+    let path = PASSWORDFILE.get().unwrap();
+    let dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    if dump.is_sealed() {
+        fail!(Failure, "Profile metadata is already sealed");
+        return;
+    }
+
+    say!("[#] Every profile has to be unlocked once to seal the vault");
+    let unlocked = match unlock_profiles(path, dump.profiles.keys().cloned().collect()) {
+        Some(x) => x,
+        None => return,
+    };
+
+    if let Err(err) = vault::seal_metadata(path, &unlocked) {
+        report_load_error(err);
+        return;
+    }
+
+    say!("[+] Profile names and records are sealed, any master password unlocks the profile list");
+    log!(INFO, "Profile metadata was sealed");
+}
+
+fn unseal_metadata() {
+    let path = PASSWORDFILE.get().unwrap();
+    let dump = match DumpFile::load_dumpfile(path) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let index_key = match index_password(&dump) {
+        Some(x) => x,
+        None => {
+            fail!(Failure, "Profile metadata isn't sealed");
+            return;
+        }
+    };
+
+    let names = match dump.profile_names(Some(&index_key)) {
+        Ok(x) => x,
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let unlocked = match unlock_profiles(path, names) {
+        Some(x) => x,
+        None => return,
+    };
+
+    if let Err(err) = vault::unseal_metadata(path, &index_key, &unlocked) {
+        report_load_error(err);
+        return;
+    }
+
+    say!("[+] Profile metadata is stored in plaintext again");
+    log!(INFO, "Profile metadata was unsealed");
+}
+
+/* (name, master password, records) of every profile, None if any of them fails to unlock */
+fn unlock_profiles(path: &str, names: Vec<String>) -> Option<Vec<(String, String, Vec<vault::Record>)>> {
+    let mut unlocked = Vec::new();
+
+    for name in names {
+        let password = prompt_master_password(
+            format!("[+] Enter master password for `{}` profile: ", name), Some(&name)
+        )?;
+
+        match vault::load(path, &password, Some(&name)) {
+            Ok(records) => unlocked.push((name, password, records.unwrap_or_default())),
+            Err(err) => {
+                report_load_error(err);
+                return None;
+            }
+        }
+    }
+
+    Some(unlocked)
+}
+
+#[allow(non_snake_case)]
+#[derive(Tabled)]
+struct Backups {
+    S_no: usize,
+    Id: String,
+    Saved: String,
+    Size: String,
+}
+
+fn list_backups() {
+    let backups = match backups::list(PASSWORDFILE.get().unwrap()) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    if backups.is_empty() {
+        fail!(NotFound, "No backups were found, one is saved every time the vault changes");
+        return;
+    }
+
+    if output::format() == output::Format::Json {
+        let backups: Vec<serde_json::Value> = backups.iter()
+            .map(|x| serde_json::json!({ "id": x.id, "saved": x.time, "size": x.size }))
+            .collect();
+        return output::json(backups);
+    }
+
+    let backups: Vec<Backups> = backups.into_iter()
+        .enumerate()
+        .map(|(idx, x)| Backups {
+            S_no: idx + 1,
+            Id: x.id,
+            Saved: x.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            Size: format!("{:.1} KiB", x.size as f64 / 1024.0),
+        }).collect();
+
+    let mut table = Table::new(backups);
+    table.with(Style::rounded());
+
+    say!("{table}");
+}
+
+/* Compares one profile of a backup with the current vault, records are matched by id
+ * (or entry and username for backups from before ids existed) */
+fn diff_backup(id: String, profile: Option<&String>) {
+    let path = PASSWORDFILE.get().unwrap();
+    let backup = match backups::find(path, &id) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    let snapshot = match DumpFile::load_snapshot(&backup.path.to_string_lossy()) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: Backup {} can't be read: {err}", backup.id);
+            return;
+        }
+    };
+
+    let password = match prompt_master_password("[+] Enter master password: ".to_string(), profile) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let current = match vault::load(path, &password, profile) {
+        Ok(y) => y.unwrap_or_default(),
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    // the master password may have changed since the backup was taken, a password handed over
+    // with --master-password-fd or RSAFE_MASTER_PASSWORD_FILE is the only one there is
+    let old = match vault::open_records(&snapshot, &password, profile) {
+        Err(vault::VaultError::WrongPassword) if MASTER_PASSWORD.get().is_none() => {
+            match rpassword::prompt_password("[+] Enter the master password the backup was made with: ") {
+                Ok(password) => vault::open_records(&snapshot, &password, profile),
+                Err(err) => {
+                    fail!(Failure, "Error: {err}");
+                    return;
+                }
+            }
+        },
+        x => x,
+    };
+
+    let old = match old {
+        Ok(x) => x,
+        Err(err) => {
+            report_load_error(err);
+            return;
+        }
+    };
+
+    let same_record = |a: &vault::Record, b: &vault::Record| a.id() == b.id()
+        || (a.entry() == b.entry() && a.username() == b.username());
+
+    // (change, record, changed fields)
+    let mut changes: Vec<(&str, &vault::Record, Vec<&str>)> = Vec::new();
+    for record in &old {
+        match current.iter().find(|x| same_record(x, record)) {
+            None => changes.push(("removed", record, Vec::new())),
+            Some(x) => {
+                let fields = changed_fields(record, x);
+                if !fields.is_empty() {
+                    changes.push(("changed", record, fields));
+                }
+            },
+        }
+    }
+    for record in current.iter().filter(|x| !old.iter().any(|y| same_record(x, y))) {
+        changes.push(("added", record, Vec::new()));
+    }
+
+    if output::format() == output::Format::Json {
+        let changes: Vec<serde_json::Value> = changes.iter()
+            .map(|(change, record, fields)| serde_json::json!({
+                "change": change, "id": record.id(), "entry": record.entry(), "username": record.username(), "fields": fields
+            }))
+            .collect();
+        output::json(serde_json::json!({ "backup": backup.id, "saved": backup.time, "changes": changes }));
+        log!(INFO, format!("Backup {} was compared", backup.id));
+        return;
+    }
+
+    say!("[#] Changes from backup {} ({}) to the current vault:", backup.id, backup.time.format("%Y-%m-%d %H:%M:%S"));
+    for (change, record, fields) in &changes {
+        match *change {
+            "removed" => say!("  - {} ({}) was removed", record.entry(), record.username()),
+            "changed" => say!("  ~ {} ({}) changed: {}", record.entry(), record.username(), fields.join(", ")),
+            _ => say!("  + {} ({}) was added", record.entry(), record.username()),
+        }
+    }
+
+    if changes.is_empty() {
+        say!("  no changes");
+    }
+
+    log!(INFO, format!("Backup {} was compared", backup.id));
+}
+
+fn changed_fields(old: &vault::Record, new: &vault::Record) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if old.entry() != new.entry() { fields.push("entry"); }
+    if old.username() != new.username() { fields.push("username"); }
+    if old.password() != new.password() { fields.push("password"); }
+    if old.email() != new.email() { fields.push("email"); }
+    if old.note() != new.note() { fields.push("note"); }
+    if old.tags() != new.tags() { fields.push("tags"); }
+    if old.folder() != new.folder() { fields.push("folder"); }
+    if old.fields() != new.fields() { fields.push("fields"); }
+    if old.otp() != new.otp() { fields.push("otp"); }
+    if old.attachments() != new.attachments() { fields.push("attachments"); }
+    if old.kind() != new.kind() { fields.push("kind"); }
+    if old.details() != new.details() { fields.push("details"); }
+    if old.urls() != new.urls() { fields.push("urls"); }
+    if old.rotate_days() != new.rotate_days() { fields.push("rotation"); }
+    fields
+}
+
+fn restore_backup(id: String) {
+    let path = PASSWORDFILE.get().unwrap();
+    let backup = match backups::find(path, &id) {
+        Ok(x) => x,
+        Err(err) => {
+            fail!(Failure, "Error: {err}");
+            return;
+        }
+    };
+
+    // a backup that doesn't parse would leave an unusable vault behind
+    if let Err(err) = DumpFile::load_snapshot(&backup.path.to_string_lossy()) {
+        fail!(Failure, "Error: Backup {} can't be read: {err}", backup.id);
+        return;
+    }
+
+    ask!("[+] Replace the vault with backup {} from {} ? (Y/N)", backup.id, backup.time.format("%Y-%m-%d %H:%M:%S"));
+    if !'y'.eq_ignore_ascii_case(&fgets().chars().next().unwrap_or('n')) {
+        say!("[$] The vault wasn't changed!");
+        return;
+    }
+
+    let restored = fs::read(&backup.path)
+        .map_err(|e| format!("{e}"))
+        .and_then(|data| storage::write_atomic(path, &data))
+        .and_then(|_| backups::snapshot(path));
+
+    if let Err(err) = restored {
+        fail!(Failure, "Error: {err}");
+        return;
+    }
+
+    say!("[+] Vault was restored from backup {} (the replaced version is in dump.json.bak)", backup.id);
+    log!(INFO, format!("Vault was restored from backup {}", backup.id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Search;
+
+    fn records(entries: &[&str]) -> Vec<vault::Record> {
+        entries.iter().map(|x| vault::Record::new(&[x.to_string(), "alice".to_string()])).collect()
+    }
+
+    fn query(text: &str) -> Query {
+        Query { text: text.to_string(), search: Search::parse(text).unwrap(), ..Default::default() }
+    }
+
+    #[test]
+    fn records_are_picked_by_id_prefix() {
+        let records = records(&["github", "github"]);
+        let id = records[1].id();
+        assert_ne!(records[0].id(), id);
+
+        // the text is ignored once an id is given
+        let by_id = Query { id: Some(id[..8].to_string()), ..query("gitlab") };
+        assert_eq!(find_records(&records, &by_id), [1]);
+
+        let by_full_id = Query { id: Some(id.clone()), ..query("") };
+        assert_eq!(find_records(&records, &by_full_id), [1]);
+
+        let unknown = Query { id: Some("ffffffff-0000".to_string()), ..query("") };
+        assert!(find_records(&records, &unknown).is_empty());
+    }
+
+    #[test]
+    fn exact_names_skip_the_search() {
+        let records = records(&["github", "GitHub Enterprise", "gitlab"]);
+
+        assert_eq!(find_records(&records, &query("github")), [0, 1]);
+        assert_eq!(find_records(&records, &Query { exact: true, ..query("GitHub") }), [0]);
+        assert!(find_records(&records, &Query { exact: true, ..query("git") }).is_empty());
+    }
+}
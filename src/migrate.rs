@@ -0,0 +1,282 @@
+use serde_json::{Map, Value, json};
+use std::{
+    fs, time::{SystemTime, UNIX_EPOCH}
+};
+
+/* Version of the dump.json layout this build reads and writes */
+pub const FORMAT_VERSION: u64 = 5;
+
+struct Migration {
+    from: u64,
+    summary: &'static str,
+    run: fn(&mut Value) -> Result<Vec<String>, String>,
+}
+
+/* One step per format version, applied in order until FORMAT_VERSION is reached */
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        summary: "group the records, kdf parameters and hmac of each profile into one object",
+        run: v1_to_v2,
+    },
+    Migration {
+        from: 2,
+        summary: "allow an encrypted profile index (--seal-metadata), existing profiles are unchanged",
+        run: v2_to_v3,
+    },
+    Migration {
+        from: 3,
+        summary: "allow file attachments, each profile lists the blobs its records use",
+        run: v3_to_v4,
+    },
+    Migration {
+        from: 4,
+        summary: "allow hkdf subkeys, each profile moves to them (and to argon2id) the next time it is stored",
+        run: v4_to_v5,
+    },
+];
+
+/* Files written before the version header existed are format 1 */
+pub fn version(value: &Value) -> u64 {
+    value.get("version").and_then(Value::as_u64).unwrap_or(1)
+}
+
+pub fn check_version(version: u64) -> Result<(), String> {
+    if version > FORMAT_VERSION {
+        return Err(format!(
+            "dump.json uses format v{} but this rsafe only understands up to v{}, please upgrade rsafe",
+            version, FORMAT_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/* Upgrades the raw file in place step by step, returns a report of what changed */
+pub fn migrate(value: &mut Value) -> Result<Vec<String>, String> {
+    let mut version = version(value);
+    check_version(version)?;
+
+    let mut report = Vec::new();
+    while version < FORMAT_VERSION {
+        let step = MIGRATIONS.iter()
+            .find(|x| x.from == version)
+            .ok_or(format!("No migration exists from format v{}", version))?;
+
+        report.push(format!("v{} -> v{}: {}", version, version + 1, step.summary));
+        report.extend((step.run)(value)?.into_iter().map(|x| format!("  - {x}")));
+
+        version += 1;
+        value["version"] = json!(version);
+    }
+
+    Ok(report)
+}
+
+/* Copies the file aside before it is rewritten, returns the backup path */
+pub fn backup(path: &str, version: u64) -> Result<String, String> {
+    let time = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_millis(),
+        Err(_) => panic!("[!] Error: SytemTime Before UNIX_EPOCH"),
+    };
+
+    let backup_path = format!("{}.v{}-{}.bak", path, version, time);
+    fs::copy(path, &backup_path).map_err(|e| format!("Backup failed: {e}"))?;
+    Ok(backup_path)
+}
+
+/* v1: { default, profiles: { name: [records] }, kdf: { name: params }, macs: { name: hmac }, wraps: { name: key }, mac }
+ * v2: { version, default, profiles: { name: { kdf, mac, wrap, records } }, mac } */
+fn v1_to_v2(value: &mut Value) -> Result<Vec<String>, String> {
+    let file = value.as_object_mut().ok_or("dump.json is not an object")?;
+    let mut changes = Vec::new();
+
+    let take_map = |file: &mut Map<String, Value>, key: &str| match file.remove(key) {
+        Some(Value::Object(x)) => Ok(x),
+        Some(Value::Null) | None => Ok(Map::new()),
+        Some(_) => Err(format!("'{}' is not an object", key)),
+    };
+
+    let profiles = take_map(file, "profiles")?;
+    let mut kdf = take_map(file, "kdf")?;
+    let mut macs = take_map(file, "macs")?;
+    let mut wraps = take_map(file, "wraps")?;
+    let mut new_profiles = Map::new();
+
+    for (name, records) in profiles {
+        let records = match records {
+            Value::Array(x) => x,
+            _ => return Err(format!("Records of profile '{}' are not a list", name)),
+        };
+
+        let mut profile = Map::new();
+
+        match kdf.remove(&name) {
+            Some(params) => { profile.insert("kdf".to_string(), params); },
+            None => changes.push(format!(
+                "profile '{}': keeps the legacy sha256 key derivation until it is next stored, then moves to argon2id", name
+            )),
+        }
+
+        if let Some(mac) = macs.remove(&name) {
+            profile.insert("mac".to_string(), mac);
+        }
+
+        if let Some(wrap) = wraps.remove(&name) {
+            profile.insert("wrap".to_string(), wrap);
+        }
+
+        let legacy = records.iter().filter(|x| x.get("entry").is_some()).count();
+        if legacy > 0 {
+            changes.push(format!(
+                "profile '{}': {} record(s) in the old layout will be re-encrypted the next time it is unlocked",
+                name, legacy
+            ));
+        }
+
+        changes.push(format!("profile '{}': {} record(s) moved", name, records.len()));
+        profile.insert("records".to_string(), Value::Array(records));
+        new_profiles.insert(name, Value::Object(profile));
+    }
+
+    file.insert("profiles".to_string(), Value::Object(new_profiles));
+    Ok(changes)
+}
+
+/* v3 only adds the optional `index` and per profile `blob`, older builds must not read sealed files */
+fn v2_to_v3(_value: &mut Value) -> Result<Vec<String>, String> {
+    Ok(Vec::new())
+}
+
+/* v4 only adds the optional per profile `attachments`, older builds would drop it and the blobs with it */
+fn v3_to_v4(_value: &mut Value) -> Result<Vec<String>, String> {
+    Ok(Vec::new())
+}
+
+/* v5 only adds the optional `subkeys`, older builds would read hkdf keys as sha256 ones and fail every check */
+fn v4_to_v5(_value: &mut Value) -> Result<Vec<String>, String> {
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+    use crate::vault::{self, DumpFile, VaultError};
+
+    /* Two records as rsafe <= 1.8.2 wrote them, the master password is "hunter2" */
+    const V1_VAULT: &str = r#"
+    {
+        "default": "personal",
+        "profiles": {
+            "personal": [
+                {
+                    "email": "fe903e5defbd2b34b9b56eb3a77f198311dd335c430cac31f9c7e854d8be0a424f",
+                    "entry": "f8952356ff9f6b7c9d0ca93623fa286ef08db029afb6",
+                    "hmac": "a2fad00e1e2cd509c032f838800f9e78666a8582fa89a164b849f5745ab973d9",
+                    "note": null,
+                    "password": "eccf344cef896fed940be6df966f83ff7f0fd01aa6ed74",
+                    "salt": "64c4432f45e0e844ef6fed78",
+                    "username": "fe903e5def6a92a9426994678b31274f48ffc11d36"
+                },
+                {
+                    "email": null,
+                    "entry": "913c1309440dd20b5196f882e7b385a3ca8eeded",
+                    "hmac": "584f7e39f5bc4daad2efbc2886fe9eefd110778b5754213ca2658eca32ca3011",
+                    "note": "8334134275d9ee76e693edb5f81b56c167f5d25478b390b8",
+                    "password": "831d0e11649ced30afff1aab947dbd965b07468ac90c2569cb",
+                    "salt": "0d6805d1b6a38e9150d65938",
+                    "username": "91321fa25b3bbbd5df179dbb197c04525656d3"
+                }
+            ]
+        }
+    }
+    "#;
+
+    /* A dump.json of its own in the temp directory, migrations write their backups next to it */
+    fn temp_vault(name: &str, data: &str) -> String {
+        let dir = env::temp_dir().join(format!("rsafe-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("dump.json");
+        fs::write(&path, data).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    type Fields = (String, String, String, Option<String>, Option<String>);
+
+    fn entries(records: &[vault::Record]) -> Vec<Fields> {
+        records.iter()
+            .map(|x| (x.entry(), x.username(), x.password(), x.email(), x.note()))
+            .collect()
+    }
+
+    #[test]
+    fn v1_is_migrated_step_by_step() {
+        let mut value: Value = serde_json::from_str(V1_VAULT).unwrap();
+        assert_eq!(version(&value), 1);
+
+        let report = migrate(&mut value).unwrap();
+        assert_eq!(version(&value), FORMAT_VERSION);
+        assert!(report[0].starts_with("v1 -> v2"));
+        assert_eq!(report.iter().filter(|x| x.starts_with('v')).count() as u64, FORMAT_VERSION - 1);
+
+        let profile = &value["profiles"]["personal"];
+        assert_eq!(profile["records"].as_array().unwrap().len(), 2);
+        assert!(profile.get("kdf").is_none());
+        assert_eq!(value["default"], "personal");
+    }
+
+    #[test]
+    fn v1_wraps_move_into_their_profiles() {
+        let mut value = json!({
+            "profiles": { "main": [], "work": [] },
+            "macs": { "main": "00", "work": "11" },
+            "wraps": { "main": "22" },
+            "mac": "33",
+        });
+        migrate(&mut value).unwrap();
+
+        assert_eq!(value["profiles"]["main"]["wrap"], "22");
+        assert!(value["profiles"]["work"].get("wrap").is_none());
+        assert_eq!(value["profiles"]["work"]["mac"], "11");
+        assert_eq!(value["mac"], "33");
+        assert!(value.get("wraps").is_none() && value.get("macs").is_none());
+    }
+
+    #[test]
+    fn v1_vault_round_trips() {
+        let path = temp_vault("v1", V1_VAULT);
+
+        let records = vault::load(&path, "hunter2", None).unwrap().unwrap();
+        assert_eq!(entries(&records), [
+            ("github".to_string(), "alice".to_string(), "s3cret!".to_string(), Some("alice@example.com".to_string()), None),
+            ("bank".to_string(), "bob".to_string(), "p@ss w0rd".to_string(), None, Some("pin 1234".to_string())),
+        ]);
+
+        // upgraded on disk: current version, records in the new layout under a key check, argon2id and hkdf, covered by the file mac
+        let raw = DumpFile::load_raw(&path).unwrap();
+        let profile = &raw["profiles"]["personal"];
+        assert_eq!(version(&raw), FORMAT_VERSION);
+        assert!(profile["records"].as_array().unwrap().iter().all(|x| x.get("data").is_some()));
+        assert!(profile.get("check").is_some());
+        assert!(profile.get("kdf").is_some());
+        assert_eq!(profile["subkeys"], "hkdf");
+        assert_eq!(raw["subkeys"], "hkdf");
+        assert!(profile.get("wrap").is_some() && raw.get("mac").is_some());
+
+        assert_eq!(entries(&vault::load(&path, "hunter2", None).unwrap().unwrap()), entries(&records));
+        assert!(matches!(vault::load(&path, "hunter3", None), Err(VaultError::WrongPassword)));
+
+        let _ = fs::remove_dir_all(std::path::Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn newer_formats_are_refused() {
+        assert!(check_version(FORMAT_VERSION).is_ok());
+        assert!(check_version(FORMAT_VERSION + 1).is_err());
+
+        let mut value = json!({ "version": FORMAT_VERSION + 1, "profiles": {} });
+        assert!(migrate(&mut value).is_err());
+    }
+}
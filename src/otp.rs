@@ -0,0 +1,358 @@
+use serde::{Serialize, Deserialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Mac, Hmac};
+use hmac::digest::KeyInit as HmacKeyInit;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+/* One-time password settings stored (encrypted) on a record.
+ * Checked when deserialized as well, a json import can hold anything */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(try_from = "StoredOtp")]
+pub struct OtpConfig {
+    pub kind: OtpKind,
+    pub secret: String,         // base32 without padding
+    pub algorithm: OtpAlgorithm,
+    pub digits: u32,
+    pub period: u64,            // seconds, totp only
+    pub counter: u64,           // next counter, hotp only
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/* OtpConfig as read, before OtpConfig::check */
+#[derive(Deserialize)]
+struct StoredOtp {
+    kind: OtpKind,
+    secret: String,
+    algorithm: OtpAlgorithm,
+    digits: u32,
+    period: u64,
+    counter: u64,
+    #[serde(default)]
+    issuer: Option<String>,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    Totp,
+    Hotp,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+impl TryFrom<StoredOtp> for OtpConfig {
+    type Error = String;
+
+    fn try_from(x: StoredOtp) -> Result<Self, String> {
+        let config = OtpConfig {
+            kind: x.kind, secret: x.secret, algorithm: x.algorithm, digits: x.digits,
+            period: x.period, counter: x.counter, issuer: x.issuer, label: x.label,
+        };
+        config.check()?;
+        Ok(config)
+    }
+}
+
+impl OtpConfig {
+    /* Accepts an otpauth:// uri or a bare base32 secret (a totp with the usual defaults) */
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+
+        let config = match input.strip_prefix("otpauth://") {
+            Some(rest) => OtpConfig::parse_uri(rest)?,
+            None => OtpConfig {
+                kind: OtpKind::Totp,
+                secret: input.to_string(),
+                algorithm: OtpAlgorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                period: DEFAULT_PERIOD,
+                counter: 0,
+                issuer: None,
+                label: None,
+            },
+        };
+
+        let secret = normalize_secret(&config.secret)?;
+        let config = OtpConfig { secret, ..config };
+        config.check()?;
+        Ok(config)
+    }
+
+    /* Settings a code can be made with: totp() divides by the period and code() takes 10^digits */
+    fn check(&self) -> Result<(), String> {
+        if !(6..=8).contains(&self.digits) {
+            return Err(format!("Unsupported otp length {}, expected 6 to 8 digits", self.digits));
+        }
+        if self.period == 0 {
+            return Err("Otp period can't be 0".to_string());
+        }
+        match BASE32_NOPAD.decode(self.secret.as_bytes()) {
+            Ok(x) if !x.is_empty() => Ok(()),
+            _ => Err("The otp secret is not valid base32".to_string()),
+        }
+    }
+
+    /* otpauth://TYPE/LABEL?secret=...&issuer=...&algorithm=...&digits=...&period=...&counter=... */
+    fn parse_uri(rest: &str) -> Result<Self, String> {
+        let (kind, rest) = rest.split_once('/').ok_or("Invalid otpauth uri, missing the label")?;
+        let kind = match kind.to_lowercase().as_str() {
+            "totp" => OtpKind::Totp,
+            "hotp" => OtpKind::Hotp,
+            x => return Err(format!("Unknown otp type '{}', expected totp or hotp", x)),
+        };
+
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut config = OtpConfig {
+            kind,
+            secret: String::new(),
+            algorithm: OtpAlgorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            counter: 0,
+            issuer: None,
+            label: Some(percent_decode(label)).filter(|x| !x.is_empty()),
+        };
+
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value);
+            let number = |x: &str| x.parse::<u64>().map_err(|_| format!("Invalid otp {} '{}'", key, x));
+
+            match key.to_lowercase().as_str() {
+                "secret" => config.secret = value,
+                "issuer" => config.issuer = Some(value),
+                "algorithm" => config.algorithm = match value.to_uppercase().as_str() {
+                    "SHA1" => OtpAlgorithm::Sha1,
+                    "SHA256" => OtpAlgorithm::Sha256,
+                    "SHA512" => OtpAlgorithm::Sha512,
+                    x => return Err(format!("Unsupported otp algorithm '{}'", x)),
+                },
+                "digits" => config.digits = number(&value)?.min(u32::MAX as u64) as u32,
+                "period" => config.period = number(&value)?,
+                "counter" => config.counter = number(&value)?,
+                _ => {},    // image, color... from some apps
+            }
+        }
+
+        if config.secret.is_empty() {
+            return Err("The otpauth uri has no secret".to_string());
+        }
+        Ok(config)
+    }
+
+    /* Same settings as an otpauth:// uri, used by the csv export */
+    pub fn to_uri(&self) -> String {
+        let kind = match self.kind { OtpKind::Totp => "totp", OtpKind::Hotp => "hotp" };
+        let algorithm = match self.algorithm {
+            OtpAlgorithm::Sha1 => "SHA1", OtpAlgorithm::Sha256 => "SHA256", OtpAlgorithm::Sha512 => "SHA512",
+        };
+
+        let mut uri = format!(
+            "otpauth://{}/{}?secret={}&algorithm={}&digits={}",
+            kind, percent_encode(self.label.as_deref().unwrap_or("")), self.secret, algorithm, self.digits
+        );
+
+        match self.kind {
+            OtpKind::Totp => uri.push_str(&format!("&period={}", self.period)),
+            OtpKind::Hotp => uri.push_str(&format!("&counter={}", self.counter)),
+        }
+        if let Some(issuer) = &self.issuer {
+            uri.push_str(&format!("&issuer={}", percent_encode(issuer)));
+        }
+        uri
+    }
+
+    /* Current totp code and the seconds it stays valid */
+    pub fn totp(&self) -> Result<(String, u64), String> {
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_secs(),
+            Err(_) => panic!("[!] Error: SytemTime Before UNIX_EPOCH"),
+        };
+
+        let code = self.code(now / self.period)?;
+        Ok((code, self.period - now % self.period))
+    }
+
+    /* RFC 4226: hmac of the big endian counter, dynamic truncation, last `digits` decimal digits */
+    pub fn code(&self, counter: u64) -> Result<String, String> {
+        let key = BASE32_NOPAD.decode(self.secret.as_bytes()).map_err(|e| format!("Invalid otp secret: {e}"))?;
+        let message = counter.to_be_bytes();
+
+        let hash = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(&key, &message),
+            OtpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(&key, &message),
+            OtpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(&key, &message),
+        };
+
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+        let code = binary as u64 % 10u64.pow(self.digits);
+
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /* Short description for record_fmt, never includes the secret */
+    pub fn summary(&self) -> String {
+        match self.kind {
+            OtpKind::Totp => format!("otp: totp, {} digits, {}s", self.digits, self.period),
+            OtpKind::Hotp => format!("otp: hotp, {} digits, counter {}", self.digits, self.counter),
+        }
+    }
+}
+
+fn hmac<M: HmacKeyInit + Mac>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as HmacKeyInit>::new_from_slice(key).expect("[!] Error: Creating hmac");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/* Apps print secrets in lower case, with spaces or with padding */
+fn normalize_secret(secret: &str) -> Result<String, String> {
+    let secret: String = secret.chars()
+        .filter(|x| !x.is_whitespace() && *x != '-' && *x != '=')
+        .collect::<String>()
+        .to_uppercase();
+
+    match BASE32_NOPAD.decode(secret.as_bytes()) {
+        Ok(x) if !x.is_empty() => Ok(secret),
+        _ => Err("The otp secret is not valid base32".to_string()),
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::new();
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        let escaped = bytes.get(idx + 1..idx + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match (bytes[idx], escaped) {
+            (b'%', Some(x)) => { out.push(x); idx += 3; },
+            (b'+', _) => { out.push(b' '); idx += 1; },
+            (x, _) => { out.push(x); idx += 1; },
+        }
+    }
+
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'@' => (x as char).to_string(),
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* The shared secrets of the RFC test vectors, one per hash length */
+    const SHA1_KEY: &[u8] = b"12345678901234567890";
+    const SHA256_KEY: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_KEY: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    fn config(kind: OtpKind, algorithm: OtpAlgorithm, key: &[u8], digits: u32) -> OtpConfig {
+        OtpConfig {
+            kind, secret: BASE32_NOPAD.encode(key), algorithm, digits,
+            period: DEFAULT_PERIOD, counter: 0, issuer: None, label: None,
+        }
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226() {
+        let codes = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489",
+        ];
+        let hotp = config(OtpKind::Hotp, OtpAlgorithm::Sha1, SHA1_KEY, 6);
+
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp.code(counter as u64).unwrap(), *code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238() {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        let sha1 = config(OtpKind::Totp, OtpAlgorithm::Sha1, SHA1_KEY, 8);
+        let sha256 = config(OtpKind::Totp, OtpAlgorithm::Sha256, SHA256_KEY, 8);
+        let sha512 = config(OtpKind::Totp, OtpAlgorithm::Sha512, SHA512_KEY, 8);
+
+        for (time, code1, code256, code512) in vectors {
+            let counter = time / DEFAULT_PERIOD;
+            assert_eq!(sha1.code(counter).unwrap(), code1, "sha1 at {}", time);
+            assert_eq!(sha256.code(counter).unwrap(), code256, "sha256 at {}", time);
+            assert_eq!(sha512.code(counter).unwrap(), code512, "sha512 at {}", time);
+        }
+    }
+
+    #[test]
+    fn uri_round_trips() {
+        let uri = "otpauth://totp/ACME%20Co:alice%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60";
+        let otp = OtpConfig::parse(uri).unwrap();
+
+        assert_eq!(otp.kind, OtpKind::Totp);
+        assert_eq!(otp.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!((otp.digits, otp.period), (8, 60));
+        assert_eq!(otp.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(otp.label.as_deref(), Some("ACME Co:alice@example.com"));
+        assert_eq!(OtpConfig::parse(&otp.to_uri()).unwrap(), otp);
+    }
+
+    #[test]
+    fn bare_secrets_are_normalized() {
+        let otp = OtpConfig::parse("jbsw y3dp-ehpk 3pxp====").unwrap();
+
+        assert_eq!(otp.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!((otp.kind, otp.digits, otp.period), (OtpKind::Totp, DEFAULT_DIGITS, DEFAULT_PERIOD));
+    }
+
+    #[test]
+    fn unusable_settings_are_rejected() {
+        assert!(OtpConfig::parse("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=20").is_err());
+        assert!(OtpConfig::parse("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=0").is_err());
+        assert!(OtpConfig::parse("otpauth://totp/x?secret=not-base32!").is_err());
+        assert!(OtpConfig::parse("otpauth://totp/x").is_err());
+    }
+
+    #[test]
+    fn imported_settings_are_checked() {
+        let stored = |digits: u32, period: u64, secret: &str| serde_json::from_value::<OtpConfig>(serde_json::json!({
+            "kind": "totp", "secret": secret, "algorithm": "SHA1", "digits": digits, "period": period, "counter": 0,
+        }));
+
+        assert!(stored(6, 30, "JBSWY3DPEHPK3PXP").is_ok());
+        assert!(stored(20, 30, "JBSWY3DPEHPK3PXP").is_err());
+        assert!(stored(6, 0, "JBSWY3DPEHPK3PXP").is_err());
+        assert!(stored(6, 30, "").is_err());
+    }
+}
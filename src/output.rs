@@ -0,0 +1,165 @@
+use std::{
+    fmt::Display, io::{self, Write}, sync::{OnceLock, atomic::{AtomicI32, Ordering}}
+};
+use clap::ValueEnum;
+use serde_json::json;
+
+/* How results, messages and errors are printed, chosen with --format.
+ * json keeps stdout for json documents only, everything else goes to stderr */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Plain,      // no tables or [+] prefixes, one value per line / tab separated
+}
+
+/* Process exit codes, documented in the README */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Failure = 1,    // anything not covered below
+    Usage = 2,      // invalid arguments, clap exits with 2 as well
+    NotFound = 3,   // no vault, profile, record or backup matched
+    Auth = 4,       // wrong master password, or banned after too many
+    Integrity = 5,  // the vault failed its hmac checks
+    Locked = 6,     // another rsafe process held the vault lock
+    Input = 7,      // an invalid value was given or typed
+}
+
+/* Which secrets (login passwords, hidden custom fields, secret values of a kind) are shown
+ * in clear, from --reveal and --reveal-field. Without either the config decides */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reveal {
+    All,
+    Fields(Vec<String>),    // password, a custom field or a value of the kind like cvv
+}
+
+/* What a masked secret looks like */
+pub const MASK: &str = "••••••";
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+static REVEAL: OnceLock<Reveal> = OnceLock::new();
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+impl ErrorCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::Failure => "failure",
+            ErrorCode::Usage => "usage",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Auth => "auth",
+            ErrorCode::Integrity => "integrity",
+            ErrorCode::Locked => "locked",
+            ErrorCode::Input => "invalid_input",
+        }
+    }
+}
+
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+pub fn set_reveal(reveal: Reveal) {
+    let _ = REVEAL.set(reveal);
+}
+
+/* Whether the secret named `field` is printed as is, or as MASK */
+pub fn reveals(field: &str) -> bool {
+    match REVEAL.get() {
+        Some(Reveal::All) => true,
+        Some(Reveal::Fields(fields)) => fields.iter().any(|x| x.eq_ignore_ascii_case(field)),
+        None => crate::config::get().reveal_secrets,
+    }
+}
+
+/* The code of the first error, 0 when the command succeeded */
+pub fn exit_code() -> i32 {
+    EXIT_CODE.load(Ordering::Relaxed)
+}
+
+/* "[+] Saved" -> "Saved" for plain output */
+fn strip_prefix(message: &str) -> &str {
+    match message.as_bytes() {
+        [b'[', _, b']', b' ', ..] => &message[4..],
+        _ => message,
+    }
+}
+
+/* A failed command: printed to stderr and remembered for the exit code */
+pub fn error(code: ErrorCode, message: impl Display) {
+    let message = message.to_string();
+    let _ = EXIT_CODE.compare_exchange(0, code as i32, Ordering::Relaxed, Ordering::Relaxed);
+
+    match format() {
+        Format::Table => eprintln!("[!] {}", message),
+        Format::Plain => eprintln!("error: {}", message.strip_prefix("Error: ").unwrap_or(&message)),
+        Format::Json => eprintln!("{}", json!({
+            "error": { "code": code.name(), "exit": code as i32, "message": message.strip_prefix("Error: ").unwrap_or(&message) }
+        })),
+    }
+}
+
+/* Something went wrong but the command goes on (a retried prompt, a failed backup) */
+pub fn warn(message: impl Display) {
+    match format() {
+        Format::Table => eprintln!("[!] {}", message),
+        Format::Plain => eprintln!("warning: {}", message),
+        Format::Json => eprintln!("{}", json!({ "warning": message.to_string() })),
+    }
+}
+
+/* Progress and confirmations, kept off stdout in json mode */
+pub fn say(message: impl Display) {
+    let message = message.to_string();
+    match format() {
+        Format::Table => println!("{}", message),
+        Format::Plain => println!("{}", strip_prefix(&message)),
+        Format::Json => eprintln!("{}", message),
+    }
+}
+
+/* A prompt, the answer is read right after so it has to be flushed */
+pub fn ask(message: impl Display) {
+    let message = message.to_string();
+    match format() {
+        Format::Table => print!("{}", message),
+        Format::Plain => print!("{}", strip_prefix(&message)),
+        Format::Json => eprint!("{}", message),
+    }
+    let _ = io::stdout().flush();
+}
+
+/* A json document on stdout */
+pub fn json(value: impl serde::Serialize) {
+    match serde_json::to_string_pretty(&value) {
+        Ok(x) => println!("{}", x),
+        Err(err) => error(ErrorCode::Failure, format!("Error: {err}")),
+    }
+}
+
+#[macro_export]
+macro_rules! fail {
+    ($code:ident, $($arg:tt)*) => {
+        $crate::output::error($crate::output::ErrorCode::$code, format!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::output::warn(format!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! say {
+    () => { $crate::output::say("") };
+    ($($arg:tt)*) => { $crate::output::say(format!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! ask {
+    ($($arg:tt)*) => { $crate::output::ask(format!($($arg)*)) };
+}
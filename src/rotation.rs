@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local, TimeDelta, Utc};
+
+use crate::kinds::RecordKind;
+use crate::vault::Record;
+
+/* Longest policy or --due window accepted from the command line, a century */
+pub const MAX_DAYS: u32 = 36500;
+
+/* Days a login may keep its password: its own policy wins over the profile's, 0 exempts it */
+pub fn policy(record: &Record, profile_days: Option<u32>) -> Option<u32> {
+    if record.kind() != RecordKind::Login {
+        return None;
+    }
+
+    match record.rotate_days().or(profile_days) {
+        Some(0) | None => None,
+        x => x,
+    }
+}
+
+/* When the password has to be changed, records from before timestamps existed are already overdue.
+ * A policy from the file reaching past what chrono can represent is never due */
+pub fn deadline(record: &Record, profile_days: Option<u32>) -> Option<DateTime<Utc>> {
+    let days = policy(record, profile_days)?;
+    let changed = record.password_changed_at()
+        .or(record.created_at())
+        .unwrap_or(DateTime::UNIX_EPOCH);
+
+    changed.checked_add_signed(TimeDelta::days(days as i64))
+}
+
+/* Indexes of the records due within `days` (0 = only those past their deadline), soonest first */
+pub fn due(records: &[Record], profile_days: Option<u32>, days: u32) -> Vec<usize> {
+    let limit = Utc::now().checked_add_signed(TimeDelta::days(days as i64)).unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+    let mut found: Vec<(usize, DateTime<Utc>)> = records.iter()
+        .enumerate()
+        .filter_map(|(idx, record)| Some((idx, deadline(record, profile_days)?)))
+        .filter(|(_, deadline)| *deadline <= limit)
+        .collect();
+
+    found.sort_by_key(|(_, deadline)| *deadline);
+    found.into_iter().map(|(idx, _)| idx).collect()
+}
+
+/* "overdue by 3 day(s)", "due today", "due in 12 day(s)", counted in calendar days */
+pub fn describe(record: &Record, profile_days: Option<u32>) -> String {
+    let deadline = match deadline(record, profile_days) {
+        Some(x) => x,
+        None => return "no rotation policy".to_string(),
+    };
+
+    if record.password_changed_at().or(record.created_at()).is_none() {
+        return "overdue, last changed before rsafe tracked it".to_string();
+    }
+
+    let days = (deadline.with_timezone(&Local).date_naive() - Local::now().date_naive()).num_days();
+    match days {
+        x if x < 0 => format!("overdue by {} day(s)", -x),
+        0 if deadline < Utc::now() => "overdue since today".to_string(),
+        0 => "due today".to_string(),
+        x => format!("due in {} day(s)", x),
+    }
+}
+
+/* Printed by the commands showing records to a person, see overdue_warning in main.rs */
+pub fn warn_overdue(records: &[Record], profile_days: Option<u32>) {
+    let overdue = due(records, profile_days, 0).len();
+    if overdue > 0 {
+        warn!("{} password(s) are past their rotation deadline, see --list --expired", overdue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /* A login last changed `ago` days back (None = before timestamps existed) with its own policy */
+    fn login(ago: Option<i64>, days: Option<u32>) -> Record {
+        let mut record = Record::new(&["github".to_string(), "alice".to_string(), "hunter2".to_string()]);
+        record.set_rotation(days);
+
+        let mut value = serde_json::to_value(&record).unwrap();
+        let changed = ago.map(|x| Value::from((Utc::now() - TimeDelta::days(x)).to_rfc3339()));
+        value["password_changed_at"] = changed.clone().unwrap_or(Value::Null);
+        value["created_at"] = changed.unwrap_or(Value::Null);
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn record_policy_wins_over_the_profile() {
+        assert_eq!(policy(&login(Some(0), Some(30)), Some(90)), Some(30));
+        assert_eq!(policy(&login(Some(0), None), Some(90)), Some(90));
+        assert_eq!(policy(&login(Some(0), Some(0)), Some(90)), None);
+        assert_eq!(policy(&login(Some(0), None), None), None);
+    }
+
+    #[test]
+    fn deadline_counts_from_the_last_change() {
+        let record = login(Some(10), Some(30));
+        let left = deadline(&record, None).unwrap() - Utc::now();
+
+        assert!(left <= TimeDelta::days(20) && left > TimeDelta::days(19));
+        assert_eq!(describe(&record, None), "due in 20 day(s)");
+        assert_eq!(describe(&login(Some(40), Some(30)), None), "overdue by 10 day(s)");
+    }
+
+    #[test]
+    fn untracked_passwords_are_overdue() {
+        let record = login(None, Some(30));
+
+        assert_eq!(deadline(&record, None), DateTime::UNIX_EPOCH.checked_add_signed(TimeDelta::days(30)));
+        assert_eq!(describe(&record, None), "overdue, last changed before rsafe tracked it");
+    }
+
+    #[test]
+    fn due_sorts_soonest_first() {
+        let records = [login(Some(5), Some(30)), login(Some(40), Some(30)), login(Some(0), None), login(Some(29), Some(30))];
+
+        assert_eq!(due(&records, None, 0), [1]);
+        assert_eq!(due(&records, None, 7), [1, 3]);
+        assert_eq!(due(&records, Some(10), 30), [1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn huge_policies_are_never_due() {
+        let records = [login(Some(0), Some(u32::MAX)), login(Some(40), Some(30))];
+
+        assert_eq!(deadline(&records[0], None), None);
+        assert_eq!(due(&records, None, u32::MAX), [1]);
+    }
+}
@@ -0,0 +1,359 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::vault::{FieldKind, Record};
+
+/* The <name> of get, edit, list... as a search: terms separated by spaces all have to match,
+ * OR (or |) separates alternatives. A term is text, "a quoted phrase" or /a regex/,
+ * scoped to one field with `user:alice`, `email:@corp.com`, `tag:prod`... */
+#[derive(Debug, Default)]
+pub struct Search {
+    groups: Vec<Vec<Term>>,     // or of ands, empty matches everything
+}
+
+#[derive(Debug)]
+struct Term {
+    scope: Option<Scope>,   // None searches entry, username, email, note and urls
+    pattern: Pattern,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Text(String),   // lowercased
+    Regex(Regex),   // case insensitive
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Entry,
+    Username,
+    Email,
+    Note,
+    Url,
+    Tag,
+    Folder,
+    Kind,
+    Field,      // names and values of custom fields, hidden values are left out
+    Id,
+}
+
+const SCOPES: [(&str, Scope); 12] = [
+    ("entry", Scope::Entry), ("name", Scope::Entry),
+    ("user", Scope::Username), ("username", Scope::Username),
+    ("email", Scope::Email), ("note", Scope::Note),
+    ("url", Scope::Url), ("tag", Scope::Tag), ("folder", Scope::Folder),
+    ("kind", Scope::Kind), ("field", Scope::Field), ("id", Scope::Id),
+];
+
+/* How well a value matches a text term, out of 100 */
+const EXACT: u32 = 100;
+const PREFIX: u32 = 80;
+const WORD: u32 = 70;       // starts at a word boundary inside the value
+const SUBSTRING: u32 = 60;
+const REGEX: u32 = 70;
+const FUZZY: u32 = 40;      // the best a fuzzy match gets, for a run of adjacent characters
+
+impl Scope {
+    /* Percent of the match score a field is worth, a hit in the entry name ranks first */
+    fn weight(&self) -> u32 {
+        match self {
+            Scope::Entry | Scope::Id => 100,
+            Scope::Username | Scope::Url | Scope::Tag => 80,
+            Scope::Email => 70,
+            Scope::Folder | Scope::Kind => 60,
+            Scope::Note | Scope::Field => 50,
+        }
+    }
+
+    fn values(&self, record: &Record) -> Vec<String> {
+        match self {
+            Scope::Entry => vec![record.entry()],
+            Scope::Username => vec![record.username()],
+            Scope::Email => record.email().into_iter().collect(),
+            Scope::Note => record.note().into_iter().collect(),
+            Scope::Url => record.urls().iter().map(|x| x.url.clone()).collect(),
+            Scope::Tag => record.tags(),
+            Scope::Folder => record.folder().into_iter().collect(),
+            Scope::Kind => vec![record.kind().name().to_string()],
+            Scope::Field => record.fields().iter()
+                .flat_map(|x| match x.kind {
+                    FieldKind::Hidden => vec![x.name.clone()],
+                    _ => vec![x.name.clone(), x.value.clone()],
+                })
+                .collect(),
+            Scope::Id => vec![record.id()],
+        }
+    }
+}
+
+impl Search {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut groups = vec![Vec::new()];
+
+        for word in split(query)? {
+            match word.as_str() {
+                "OR" | "|" => groups.push(Vec::new()),
+                "AND" | "&" => {},
+                _ => groups.last_mut().unwrap().push(Term::parse(&word)?),
+            }
+        }
+
+        if groups.len() > 1 && groups.iter().any(|x| x.is_empty()) {
+            return Err(format!("'{}' has an OR without a term on one side", query));
+        }
+        groups.retain(|x| !x.is_empty());
+        Ok(Search { groups })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /* 0 when the record doesn't match, otherwise the higher the better.
+     * `fuzzy` also accepts the characters of a text term in order with gaps between them */
+    pub fn score(&self, record: &Record, fuzzy: bool) -> u32 {
+        if self.groups.is_empty() {
+            return 1;
+        }
+
+        self.groups.iter()
+            .map(|terms| {
+                let scores: Vec<u32> = terms.iter().map(|x| x.score(record, fuzzy)).collect();
+                match scores.contains(&0) {
+                    true => 0,
+                    false => scores.iter().sum(),
+                }
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Term {
+    fn parse(word: &str) -> Result<Self, String> {
+        let (scope, value) = match word.split_once(':') {
+            Some((name, value)) => match SCOPES.iter().find(|(x, _)| x.eq_ignore_ascii_case(name)) {
+                Some((_, scope)) => (Some(*scope), value),
+                None => (None, word),   // https://... is a url, not a scope
+            },
+            None => (None, word),
+        };
+
+        if value.is_empty() {
+            return Err(format!("'{}' has nothing to search for", word));
+        }
+
+        let pattern = match value.strip_prefix('/').and_then(|x| x.strip_suffix('/')) {
+            Some("") => return Err(format!("'{}' is an empty regex", word)),
+            Some(regex) => Pattern::Regex(
+                RegexBuilder::new(regex)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("Invalid regex '{}': {e}", regex))?
+            ),
+            None => Pattern::Text(value.to_lowercase()),
+        };
+
+        Ok(Term { scope, pattern })
+    }
+
+    fn score(&self, record: &Record, fuzzy: bool) -> u32 {
+        let scopes = match self.scope {
+            Some(x) => vec![x],
+            None => vec![Scope::Entry, Scope::Username, Scope::Email, Scope::Note, Scope::Url],
+        };
+
+        scopes.iter()
+            .flat_map(|scope| scope.values(record).into_iter().map(move |value| (scope, value)))
+            .map(|(scope, value)| self.pattern.score(&value, *scope, fuzzy) * scope.weight() / 100)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Pattern {
+    fn score(&self, value: &str, scope: Scope, fuzzy: bool) -> u32 {
+        let needle = match self {
+            Pattern::Regex(regex) => return if regex.is_match(value) { REGEX } else { 0 },
+            Pattern::Text(x) => x,
+        };
+        let value = value.to_lowercase();
+
+        if value == *needle {
+            EXACT
+        } else if value.starts_with(needle.as_str()) {
+            PREFIX
+        } else if scope == Scope::Id {
+            0   // ids only match from the start
+        } else if let Some(at) = value.find(needle.as_str()) {
+            match value[..at].ends_with(|x: char| !x.is_alphanumeric()) {
+                true => WORD,
+                false => SUBSTRING,
+            }
+        } else if fuzzy {
+            fuzzy_score(&value, needle)
+        } else {
+            0
+        }
+    }
+}
+
+/* Characters of `needle` in order inside `value`, scored by how close together they are */
+fn fuzzy_score(value: &str, needle: &str) -> u32 {
+    let chars: Vec<char> = value.chars().collect();
+    let mut start = None;
+    let mut pos = 0;
+
+    for x in needle.chars() {
+        match chars[pos..].iter().position(|c| *c == x) {
+            Some(found) => {
+                start.get_or_insert(pos + found);
+                pos += found + 1;
+            },
+            None => return 0,
+        }
+    }
+
+    let span = (pos - start.unwrap_or(0)).max(1) as u32;
+    (FUZZY * needle.chars().count() as u32 / span).max(1)
+}
+
+/* Words split on spaces, "double quotes" keep a phrase together */
+fn split(query: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for x in query.chars() {
+        match x {
+            '"' => quoted = !quoted,
+            x if x.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            x => word.push(x),
+        }
+    }
+
+    if quoted {
+        return Err(format!("'{}' has an unclosed quote", query));
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::urls::UrlRule;
+
+    /* entry, username, email, note */
+    fn record(values: [&str; 4]) -> Record {
+        let data: Vec<String> = [values[0], values[1], "hunter2", values[2], values[3]].iter().map(|x| x.to_string()).collect();
+        Record::new(&data)
+    }
+
+    fn score(query: &str, record: &Record) -> u32 {
+        Search::parse(query).unwrap().score(record, false)
+    }
+
+    #[test]
+    fn closer_matches_rank_higher() {
+        let exact = score("git", &record(["git", "", "", ""]));
+        let prefix = score("git", &record(["github", "", "", ""]));
+        let word = score("git", &record(["my-git-repo", "", "", ""]));
+        let substring = score("git", &record(["legit", "", "", ""]));
+
+        assert!(exact > prefix && prefix > word && word > substring && substring > 0);
+        assert_eq!(score("git", &record(["gitlab", "", "", ""])), prefix);
+        assert_eq!(score("git", &record(["gti", "", "", ""])), 0);
+    }
+
+    #[test]
+    fn entry_hits_rank_above_other_fields() {
+        let entry = score("aws", &record(["aws", "", "", ""]));
+        let username = score("aws", &record(["console", "aws", "", ""]));
+        let note = score("aws", &record(["console", "", "", "aws"]));
+
+        assert!(entry > username && username > note && note > 0);
+    }
+
+    #[test]
+    fn terms_are_anded_and_or_picks_the_best_group() {
+        let github = record(["github", "alice", "alice@work.com", ""]);
+
+        assert!(score("github alice", &github) > score("github", &github));
+        assert_eq!(score("github bob", &github), 0);
+        assert!(score("bob OR alice", &github) > 0);
+        assert!(score("bob | github", &github) > 0);
+        assert_eq!(score("github AND bob", &github), 0);
+    }
+
+    #[test]
+    fn scopes_only_look_at_their_field() {
+        let mut github = record(["github", "alice", "alice@work.com", "personal account"]);
+        github.add_tag("dev");
+        github.set_folder("code/hosting");
+        github.add_url(UrlRule::parse("github.com").unwrap()).unwrap();
+
+        assert!(score("user:alice", &github) > 0);
+        assert_eq!(score("entry:alice", &github), 0);
+        assert!(score("email:@work.com", &github) > 0);
+        assert!(score("note:personal", &github) > 0);
+        assert!(score("tag:dev", &github) > 0);
+        assert!(score("folder:code", &github) > 0);
+        assert!(score("kind:login", &github) > 0);
+        assert!(score("url:github.com", &github) > 0);
+        assert!(score(&format!("id:{}", &github.id()[..8]), &github) > 0);
+        assert_eq!(score(&format!("id:{}", &github.id()[4..12]), &github), 0);
+        // not a scope, the whole term is searched
+        assert!(score("https://github.com/", &github) > 0);
+    }
+
+    #[test]
+    fn quotes_keep_a_phrase_together() {
+        let bank = record(["bank", "", "", "my bank login"]);
+        let other = record(["bank", "", "", "login for my card"]);
+
+        assert!(score("\"my bank\"", &bank) > 0);
+        assert_eq!(score("\"my bank\"", &other), 0);
+        assert!(score("my bank", &other) > 0);
+    }
+
+    #[test]
+    fn regexes_are_case_insensitive() {
+        let ticket = record(["GH-1234", "", "", ""]);
+
+        assert!(score(r"/^gh-\d+$/", &ticket) > 0);
+        assert_eq!(score(r"/^gh-[a-z]+$/", &ticket), 0);
+        assert!(score(r"entry:/\d{4}/", &ticket) > 0);
+    }
+
+    #[test]
+    fn fuzzy_matches_only_when_asked() {
+        let github = record(["github", "", "", ""]);
+        let search = Search::parse("gthb").unwrap();
+
+        assert_eq!(search.score(&github, false), 0);
+        assert!(search.score(&github, true) > 0);
+        assert!(search.score(&github, true) < score("hub", &github));
+        assert_eq!(Search::parse("bhtg").unwrap().score(&github, true), 0);
+    }
+
+    #[test]
+    fn an_empty_search_matches_everything() {
+        let search = Search::parse("  ").unwrap();
+
+        assert!(search.is_empty());
+        assert!(search.score(&record(["anything", "", "", ""]), false) > 0);
+    }
+
+    #[test]
+    fn malformed_searches_are_errors() {
+        for query in ["\"unclosed", "github OR", "| github", "user:", "//", "/(/"] {
+            assert!(Search::parse(query).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError}, io::Write, path::Path, process, thread,
+    time::{Duration, Instant}
+};
+
+/* Exclusive advisory lock on the vault, released when dropped (or when the process dies) */
+pub struct VaultLock {
+    _file: File,
+}
+
+/* Locks `path` for the whole command so two rsafe processes can't interleave their load and dump,
+ * waits up to `timeout` for the other one to finish */
+pub fn lock_vault(path: &str, timeout: Duration) -> Result<VaultLock, String> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| format!("Error opening lock file {}: {e}", path))?;
+
+    let start = Instant::now();
+    let mut waiting = false;
+
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(VaultLock { _file: file }),
+            Err(TryLockError::WouldBlock) => {},
+            Err(TryLockError::Error(e)) => return Err(format!("Error locking {}: {e}", path)),
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(format!(
+                "Vault is busy, another rsafe process is using it (waited {}s, see lock_timeout in config.json)",
+                timeout.as_secs()
+            ));
+        }
+
+        if !waiting {
+            say!("[#] Waiting for another rsafe process to finish...");
+            waiting = true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/* Replaces `path` with `data` so that a crash leaves either the old or the new file, never a torn one.
+ * The data goes to a temp file next to it, is fsynced, the current file is copied to `<path>.bak`,
+ * then the temp file is renamed over `path` and the directory is fsynced so the rename itself persists. */
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    let target = Path::new(path);
+    let dir = match target.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+
+    let name = target.file_name()
+        .ok_or(format!("Invalid vault path '{}'", path))?
+        .to_string_lossy();
+    let tmp = dir.join(format!(".{}.tmp-{}", name, process::id()));
+    let _ = fs::remove_file(&tmp);     // left over from a crashed run that had the same pid
+
+    let result = write_tmp(&tmp, target, data)
+        .and_then(|_| backup_current(path))
+        .and_then(|_| fs::rename(&tmp, target).map_err(|e| format!("Error replacing {}: {e}", path)))
+        .and_then(|_| sync_dir(dir));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_tmp(tmp: &Path, target: &Path, data: &[u8]) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp)
+        .map_err(|e| format!("Error creating {}: {e}", tmp.display()))?;
+
+    // keep whatever permissions the user gave the vault
+    if let Ok(meta) = fs::metadata(target) {
+        file.set_permissions(meta.permissions()).map_err(|e| format!("{e}"))?;
+    }
+
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Error writing {}: {e} (the vault was not changed)", tmp.display()))
+}
+
+/* The previous good version, skipped while the file is still empty (right after --init) */
+fn backup_current(path: &str) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() > 0 => {},
+        _ => return Ok(()),
+    }
+
+    let backup = format!("{}.bak", path);
+    fs::copy(path, &backup).map_err(|e| format!("Error writing {}: {e} (the vault was not changed)", backup))?;
+    File::open(&backup)
+        .and_then(|x| x.sync_all())
+        .map_err(|e| format!("{e}"))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    File::open(dir)
+        .and_then(|x| x.sync_all())
+        .map_err(|e| format!("Error syncing {}: {e}", dir.display()))
+}
+
+// directories can't be opened (and don't need to be synced) on windows
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("rsafe-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_replace_the_file_and_keep_the_previous_one() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("dump.json").to_string_lossy().to_string();
+
+        // nothing to back up while the file is still empty
+        fs::write(&path, b"").unwrap();
+        write_atomic(&path, b"first").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!Path::new(&format!("{}.bak", path)).exists());
+
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(fs::read(format!("{}.bak", path)).unwrap(), b"first");
+
+        // no temp file is left next to the vault
+        let names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(names.iter().all(|x| !x.contains(".tmp-")), "{names:?}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_keep_the_permissions_of_the_vault() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("atomic-mode");
+        let path = dir.join("dump.json").to_string_lossy().to_string();
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_vault_alone() {
+        let dir = temp_dir("atomic-fail");
+        let path = dir.join("dump.json").to_string_lossy().to_string();
+        fs::write(&path, b"kept").unwrap();
+
+        // the backup can't be written over a directory, so the rename never happens
+        fs::create_dir(format!("{}.bak", path)).unwrap();
+        assert!(write_atomic(&path, b"lost").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"kept");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_second_lock_waits_and_then_gives_up() {
+        let dir = temp_dir("lock");
+        let path = dir.join("lock").to_string_lossy().to_string();
+
+        let held = lock_vault(&path, Duration::ZERO).unwrap();
+        let err = lock_vault(&path, Duration::from_millis(200)).err().unwrap();
+        assert!(err.contains("Vault is busy"), "{err}");
+
+        // released by drop, a waiting process gets it once the other one finishes
+        let waiter = {
+            let path = path.clone();
+            thread::spawn(move || lock_vault(&path, Duration::from_secs(10)).is_ok())
+        };
+        thread::sleep(Duration::from_millis(300));
+        drop(held);
+        assert!(waiter.join().unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
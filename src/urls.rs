@@ -0,0 +1,164 @@
+use std::sync::OnceLock;
+use serde::{Serialize, Deserialize};
+use publicsuffix::{List, Psl};
+use regex::Regex;
+use url::Url;
+
+/* A url on a login and how --lookup compares other urls to it */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct UrlRule {
+    pub url: String,
+    pub rule: MatchRule,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchRule {
+    Prefix,     // the url has to start with this one
+    Host,       // same host, mail.example.com doesn't match example.com
+    Regex,      // `url` is a regular expression over the whole url
+    #[default]
+    Domain,     // same base domain, any subdomain matches
+}
+
+/* The public suffix list (https://publicsuffix.org), private section included, so alice.github.io
+ * and bob.github.io are different sites just like bbc.co.uk and itv.co.uk.
+ * Bundled as public_suffix_list.dat, its header says which version it is */
+static SUFFIXES: OnceLock<List> = OnceLock::new();
+
+impl MatchRule {
+    fn parse(rule: &str) -> Option<Self> {
+        match rule.to_lowercase().as_str() {
+            "prefix" => Some(MatchRule::Prefix),
+            "host" => Some(MatchRule::Host),
+            "regex" => Some(MatchRule::Regex),
+            "domain" => Some(MatchRule::Domain),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchRule::Prefix => "prefix",
+            MatchRule::Host => "host",
+            MatchRule::Regex => "regex",
+            MatchRule::Domain => "domain",
+        }
+    }
+}
+
+impl UrlRule {
+    /* "[rule:]url", the rule defaults to domain and a url without a scheme gets https:// */
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let arg = arg.trim();
+        let (rule, url) = match arg.split_once(':').and_then(|(rule, rest)| Some((MatchRule::parse(rule)?, rest))) {
+            Some((rule, rest)) => (rule, rest.trim()),
+            None => (MatchRule::Domain, arg),
+        };
+
+        if rule == MatchRule::Regex {
+            Regex::new(url).map_err(|e| format!("Invalid url regex: {e}"))?;
+            return Ok(UrlRule { url: url.to_string(), rule });
+        }
+
+        let url = normalize(url)?;
+        if rule != MatchRule::Prefix && url.host_str().is_none() {
+            return Err(format!("'{}' has no host to match", url));
+        }
+        Ok(UrlRule { url: url.to_string(), rule })
+    }
+
+    pub fn matches(&self, target: &Url) -> bool {
+        let url = match self.rule {
+            MatchRule::Regex => return Regex::new(&self.url).is_ok_and(|x| x.is_match(target.as_str())),
+            _ => match Url::parse(&self.url) {
+                Ok(x) => x,
+                Err(_) => return false,
+            },
+        };
+
+        match self.rule {
+            MatchRule::Prefix => target.as_str().starts_with(url.as_str()),
+            MatchRule::Host => url.host_str().is_some() && url.host_str() == target.host_str(),
+            MatchRule::Domain => match (url.host_str(), target.host_str()) {
+                (Some(a), Some(b)) => base_domain(a) == base_domain(b),
+                _ => false,
+            },
+            MatchRule::Regex => unreachable!(),
+        }
+    }
+
+    pub fn display(&self) -> String {
+        format!("url: {} ({})", self.url, self.rule.name())
+    }
+}
+
+/* Parses what --lookup and --url are given, "github.com/login" is read as https://github.com/login */
+pub fn normalize(url: &str) -> Result<Url, String> {
+    let url = url.trim();
+    let parsed = match url.contains("://") {
+        true => Url::parse(url),
+        false => Url::parse(&format!("https://{}", url)),
+    };
+
+    parsed.map_err(|e| format!("Invalid url '{}': {e}", url))
+}
+
+/* "mail.google.com" -> "google.com", "www.bbc.co.uk" -> "bbc.co.uk", "alice.github.io" stays whole.
+ * Ip addresses and hosts without a registrable domain (localhost, a bare suffix) are compared whole */
+fn base_domain(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[') {
+        return host;
+    }
+
+    let list = SUFFIXES.get_or_init(|| {
+        include_str!("public_suffix_list.dat").parse().expect("the bundled public suffix list parses")
+    });
+    match list.domain(host.as_bytes()) {
+        Some(domain) => String::from_utf8_lossy(domain.as_bytes()).to_string(),
+        None => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(rule: &str, url: &str) -> bool {
+        UrlRule::parse(rule).unwrap().matches(&normalize(url).unwrap())
+    }
+
+    #[test]
+    fn rules_are_parsed_with_domain_as_default() {
+        assert_eq!(UrlRule::parse("github.com").unwrap(), UrlRule { url: "https://github.com/".to_string(), rule: MatchRule::Domain });
+        assert_eq!(UrlRule::parse("host:http://mail.example.com").unwrap().rule, MatchRule::Host);
+        assert_eq!(UrlRule::parse("regex:^https://(www\\.)?example\\.com/").unwrap().url, "^https://(www\\.)?example\\.com/");
+
+        assert!(UrlRule::parse("regex:(").is_err());
+        assert!(UrlRule::parse("host:file:///etc/passwd").is_err());
+    }
+
+    #[test]
+    fn domain_rules_match_the_registrable_domain() {
+        assert!(matches("github.com", "https://gist.github.com/alice"));
+        assert!(matches("www.bbc.co.uk", "bbc.co.uk/news"));
+        assert!(!matches("bbc.co.uk", "itv.co.uk"));
+        assert!(!matches("alice.github.io", "bob.github.io"));
+        assert!(matches("alice.github.io", "https://www.alice.github.io"));
+        assert!(matches("192.168.1.1", "http://192.168.1.1:8080/admin"));
+        assert!(!matches("192.168.1.1", "192.168.1.2"));
+    }
+
+    #[test]
+    fn host_prefix_and_regex_rules() {
+        assert!(matches("host:mail.example.com", "https://mail.example.com/inbox"));
+        assert!(!matches("host:example.com", "mail.example.com"));
+
+        assert!(matches("prefix:https://example.com/admin", "https://example.com/admin/users"));
+        assert!(!matches("prefix:https://example.com/admin", "https://example.com/login"));
+
+        assert!(matches("regex:^https://[a-z]+\\.example\\.com/", "https://shop.example.com/cart"));
+        assert!(!matches("regex:^https://[a-z]+\\.example\\.com/", "https://example.org/"));
+    }
+}
@@ -1,11 +1,11 @@
 use serde::{Serialize, Deserialize};
 use std::{
-    fs, io::{self, Write}, collections::HashMap
+    fs, fmt, io::{self, Write}, collections::{BTreeMap, HashMap, HashSet}, sync::OnceLock
 };
 use hex::{encode, decode};
 use hmac::{Mac, Hmac};
 use hmac::digest::KeyInit as HmacKeyInit;
-use sha2::{Sha256, Digest};
+use sha2::Sha256;
 use rand::{
     random, rng, seq::IndexedRandom
 };
@@ -13,13 +13,121 @@ use rand::seq::SliceRandom;
 use aes_gcm::{
     aead::Aead, Aes256Gcm, Key, Nonce,
 };
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
 use tabled::{
-    Tabled, Table,
+    Tabled, Table, builder::Builder,
     settings::{Style, Width, Alignment, object::Columns}
 };
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+use crate::kdf::{KdfParams, Subkeys};
+use crate::migrate;
+use crate::storage;
+use crate::backups;
+use crate::blobs;
+use crate::config;
+use crate::otp::OtpConfig;
+use crate::kinds::{self, RecordKind};
+use crate::urls::UrlRule;
+use crate::output;
+
+/* Plaintext record, serialized as a whole and encrypted as one aes-gcm message */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Record {
+    /* uuid, records stored before ids existed get one the next time the profile is unlocked */
+    #[serde(default)]
+    id: String,
+    entry: String,
+    username: String,
+    password: String,
+    email: Option<String>,
+    note: Option<String>,
+    /* None for records stored before timestamps existed */
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    password_changed_at: Option<DateTime<Utc>>,
+    /* previous passwords, newest first, encrypted along with the rest of the record */
+    #[serde(default)]
+    history: Vec<OldPassword>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /* "infra/aws", None is the top level */
+    #[serde(default)]
+    folder: Option<String>,
+    #[serde(default)]
+    fields: Vec<CustomField>,
+    /* totp/hotp secret and settings, set with --otp-set */
+    #[serde(default)]
+    otp: Option<OtpConfig>,
+    /* files encrypted into ~/.rustsafe/blobs, see blobs.rs */
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    /* records stored before kinds existed are logins */
+    #[serde(default)]
+    kind: RecordKind,
+    /* values of the kind's schema (kinds.rs), empty for logins */
+    #[serde(default)]
+    details: BTreeMap<String, String>,
+    /* sites a login is used on, see urls.rs for the match rules */
+    #[serde(default)]
+    urls: Vec<UrlRule>,
+    /* days the password may be kept, None follows the profile's policy and 0 exempts the record */
+    #[serde(default)]
+    rotate_days: Option<u32>,
+}
+
+/* Extra named value on a record, set with --field name[:kind]=value */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CustomField {
+    pub name: String,
+    pub kind: FieldKind,
+    pub value: String,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Text,
+    Hidden,     // masked in --list
+    Url,
+    Number,
+}
+
+/* names --field can't take, they already mean something on every record */
+const RESERVED_FIELDS: [&str; 7] = ["id", "entry", "username", "password", "email", "note", "folder"];
+
+/* A file added with --attach, `key` decrypts the blob `id` */
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub id: String,
+    pub key: String,
+    pub size: u64,
+    pub added_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OldPassword {
+    pub password: String,
+    pub replaced_at: Option<DateTime<Utc>>,
+}
+
+/* On-disk layout of a record: data = hex(nonce + ciphertext) with a fresh nonce for every write,
+ * hmac = hmac(salt + data) so any change to the stored record is caught before it is used */
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SealedRecord {
+    salt: String,
+    data: String,
+    hmac: String,
+}
+
+/* On-disk layout written by rsafe <= 1.8.2, every field was encrypted with the salt as nonce.
+ * Only read so old profiles can be migrated the first time they are unlocked */
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LegacyRecord {
     salt: String,
     entry: String,
     username: String,
@@ -29,285 +137,1758 @@ pub struct Record {
     hmac: String,
 }
 
-#[derive(Tabled)]
-struct TabledData {
-    entry: String,
-    username: String,
-    password: String,
-    email: String,
-    note: String,
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum StoredRecord {
+    Sealed(SealedRecord),
+    Legacy(LegacyRecord),
+}
+
+#[derive(Tabled)]
+struct TabledData {
+    id: String,
+    entry: String,
+    username: String,
+    password: String,
+    email: String,
+    note: String,
+    folder: String,
+    tags: String,
+    fields: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DumpFile {
+    pub version: u64,
+    pub default: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    /* sealed metadata: profile names and the default live in here and `profiles` is keyed by random ids */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<SealedIndex>,
+    /* hmac under the vault key over every profile holding it (and the sealed index), so a removed profile
+     * or one put back from an older copy is caught even though each profile has its own password */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /* how the keys of `mac` and the sealed index come out of the vault key */
+    #[serde(default, skip_serializing_if = "Subkeys::is_sha256")]
+    pub subkeys: Subkeys,
+    /* vault key, kept once some password opened it so writes can update `mac` and sweep blobs */
+    #[serde(skip)]
+    vault_key: OnceLock<String>,
+}
+
+/* By hand so the cached vault key never ends up in a log or a panic message */
+impl fmt::Debug for DumpFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DumpFile")
+            .field("version", &self.version)
+            .field("default", &self.default)
+            .field("profiles", &self.profiles)
+            .field("index", &self.index)
+            .field("mac", &self.mac)
+            .field("subkeys", &self.subkeys)
+            .field("vault_key", &self.vault_key.get().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SealedIndex {
+    kdf: KdfParams,     // turns any profile password into the key that unwraps the vault key
+    data: String,       // Index encrypted with the vault key
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+struct Index {
+    default: Option<String>,
+    names: HashMap<String, String>,     // profile name -> id
+    #[serde(default)]
+    attachments: HashMap<String, Vec<String>>,      // profile id -> ids of the blobs its records use
+}
+
+/* Decrypted index, keeps the vault key around so it can be written back */
+struct OpenIndex {
+    index: Index,
+    vault_key: String,
+}
+
+/* Key a profile's password turns into (argon2id output, or the password itself for legacy profiles)
+ * and how the keys for each purpose are made from it */
+struct ProfileKey {
+    key: String,
+    subkeys: Subkeys,
+}
+
+impl ProfileKey {
+    fn subkey(&self, label: &str) -> [u8; 32] {
+        self.subkeys.derive(label, &self.key)
+    }
+
+    fn record(&self, salt: &str) -> [u8; 32] {
+        self.subkeys.record(salt, &self.key)
+    }
+}
+
+/* Plaintext of a sealed metadata profile, padded before encryption so the size hides the count */
+#[derive(Serialize, Deserialize)]
+struct Blob {
+    profile: String,
+    records: Vec<Record>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Profile {
+    /* None means the legacy sha256 derivation */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>,
+    /* how the keys below (and the record keys) come out of the profile key, see Subkeys */
+    #[serde(default, skip_serializing_if = "Subkeys::is_sha256")]
+    pub subkeys: Subkeys,
+    /* hmac over the profile name, the ordered record hmacs and the wrapped vault key */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /* KEY_CHECK encrypted with the profile key, tells a wrong password apart before any record is touched */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+    /* the vault key encrypted for this profile's password (through the index kdf when sealed,
+     * with the profile key otherwise), None for profiles stored before the file mac existed */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<String>,
+    /* sealed metadata only: every record in one padded message instead of `records` */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    pub records: Vec<StoredRecord>,
+    /* ids of the blobs its records use, in plaintext so orphans can be removed without any password.
+     * Sealed profiles keep them in the index instead */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<String>,
+    /* days a login may keep its password unless the record has its own policy */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_days: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum VaultError {
+    WrongPassword,
+    Integrity { profile: String, record: Option<usize> },  // record is None when the ordering/count was changed
+    FileIntegrity,      // a profile was removed, added or rolled back behind the others' backs
+    Other(String),
+}
+
+#[allow(clippy::upper_case_acronyms, clippy::large_enum_variant)]
+pub enum RecordPrint {
+    VECTOR(Vec<Record>),
+    RECORD(Record),
+}
+
+type HmacSha256 = Hmac<Sha256>;
+const WRAP_WIDTH: usize = 40;
+const KEY_CHECK: &str = "rustsafe-key-check";
+const BLOB_PADDING: usize = 4096;
+
+impl Record {
+    /* data = [entry, username, password, email, note], missing values are empty */
+    pub fn new(data: &[String]) -> Self {
+        let value = |idx: usize| data.get(idx).cloned().unwrap_or_default();
+        let optional = |idx: usize| data.get(idx).filter(|x| !x.is_empty()).cloned();
+        let now = Some(Utc::now());
+
+        Record {
+            id: new_id(),
+            entry: value(0),
+            username: value(1),
+            password: value(2),
+            email: optional(3),
+            note: optional(4),
+            created_at: now,
+            updated_at: now,
+            password_changed_at: now,
+            history: Vec::new(),
+            tags: Vec::new(),
+            folder: None,
+            fields: Vec::new(),
+            otp: None,
+            attachments: Vec::new(),
+            kind: RecordKind::Login,
+            details: BTreeMap::new(),
+            urls: Vec::new(),
+            rotate_days: None,
+        }
+    }
+
+    fn seal(&self, key: &ProfileKey) -> Result<SealedRecord, String> {
+        let salt = encode(random_bytes(12));
+        let plaintext = serde_json::to_string(self).map_err(|e| format!("{e}"))?;
+        let data = seal_message(&key.record(&salt), &plaintext)?;
+
+        let hmac = encode(SealedRecord::mac(&salt, &data, key).finalize().into_bytes());
+        Ok(SealedRecord { salt, data, hmac })
+    }
+
+    /* Same record (keeps its id and creation time) with new contents, a replaced password goes to the history */
+    pub fn updated(&self, data: &[String]) -> Self {
+        let mut record = Record {
+            id: self.id.clone(),
+            password: self.password.clone(),
+            created_at: self.created_at,
+            password_changed_at: self.password_changed_at,
+            history: self.history.clone(),
+            tags: self.tags.clone(),
+            folder: self.folder.clone(),
+            fields: self.fields.clone(),
+            otp: self.otp.clone(),
+            attachments: self.attachments.clone(),
+            kind: self.kind,
+            details: self.details.clone(),
+            urls: self.urls.clone(),
+            rotate_days: self.rotate_days,
+            ..Record::new(data)
+        };
+
+        record.set_password(&data.get(2).cloned().unwrap_or_default());
+        record
+    }
+
+    /* Puts the n-th previous password (1 = newest) back, the current one goes into the history */
+    pub fn restore_password(&mut self, n: usize) -> Result<(), String> {
+        if n == 0 || n > self.history.len() {
+            return Err(format!("No password #{} in the history of '{}' ({} kept)", n, self.entry, self.history.len()));
+        }
+
+        let old = self.history.remove(n - 1);
+        self.set_password(&old.password);
+        self.updated_at = Some(Utc::now());
+        Ok(())
+    }
+
+    /* false if the record already had the tag */
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || self.tags.contains(&tag) {
+            return false;
+        }
+
+        self.tags.push(tag);
+        self.tags.sort();
+        true
+    }
+
+    /* false if the record didn't have the tag */
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let tag = tag.trim().to_lowercase();
+        let len = self.tags.len();
+        self.tags.retain(|x| *x != tag);
+        self.tags.len() != len
+    }
+
+    pub fn set_folder(&mut self, folder: &str) {
+        self.folder = normalize_folder(folder);
+    }
+
+    /* true if the record is in `folder` or one of its subfolders */
+    pub fn in_folder(&self, folder: &str) -> bool {
+        match (normalize_folder(folder), &self.folder) {
+            (None, _) => true,
+            (Some(x), Some(y)) => *y == x || y.starts_with(&format!("{}/", x)),
+            (Some(_), None) => false,
+        }
+    }
+
+    /* "name=value" or "name:kind=value", an empty value removes the field.
+     * Without a kind an existing field keeps its kind and a new one is text */
+    pub fn apply_field(&mut self, arg: &str) -> Result<(), String> {
+        let (name, value) = arg.split_once('=')
+            .ok_or(format!("Invalid field '{}', expected name=value or name:kind=value", arg))?;
+
+        let (name, kind) = match name.split_once(':') {
+            Some((name, kind)) => (name.trim(), Some(FieldKind::parse(kind)?)),
+            None => (name.trim(), None),
+        };
+
+        if name.is_empty() || RESERVED_FIELDS.contains(&name.to_lowercase().as_str()) {
+            return Err(format!("'{}' can't be used as a field name", name));
+        }
+
+        let existing = self.fields.iter().position(|x| x.name.eq_ignore_ascii_case(name));
+
+        if value.is_empty() {
+            return match existing {
+                Some(idx) => { self.fields.remove(idx); Ok(()) },
+                None => Err(format!("'{}' has no field '{}'", self.entry, name)),
+            };
+        }
+
+        let kind = kind
+            .or(existing.map(|idx| self.fields[idx].kind))
+            .unwrap_or(FieldKind::Text);
+        kind.validate(value)?;
+
+        let field = CustomField { name: name.to_string(), kind, value: value.to_string() };
+        match existing {
+            Some(idx) => self.fields[idx] = field,
+            None => self.fields.push(field),
+        }
+
+        self.updated_at = Some(Utc::now());
+        Ok(())
+    }
+
+    fn set_password(&mut self, password: &str) {
+        if self.password == password {
+            return;
+        }
+
+        let old = std::mem::replace(&mut self.password, password.to_string());
+        self.history.insert(0, OldPassword { password: old, replaced_at: Some(Utc::now()) });
+        self.history.truncate(config::get().history_size);
+        self.password_changed_at = Some(Utc::now());
+    }
+
+    // id entry username email note
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn entry(&self) -> String {
+        self.entry.clone()
+    }
+
+    pub fn username(&self) -> String {
+        self.username.clone()
+    }
+
+    pub fn password(&self) -> String {
+        self.password.clone()
+    }
+
+    pub fn email(&self) -> Option<String> {
+        self.email.clone()
+    }
+
+    pub fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
+
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+
+    pub fn password_changed_at(&self) -> Option<DateTime<Utc>> {
+        self.password_changed_at
+    }
+
+    pub fn history(&self) -> &[OldPassword] {
+        &self.history
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+
+    pub fn folder(&self) -> Option<String> {
+        self.folder.clone()
+    }
+
+    pub fn fields(&self) -> &[CustomField] {
+        &self.fields
+    }
+
+    /* The raw value of one field for `get --field`: the login values, a detail of the kind
+     * or a custom field (case insensitive). None when the record has no such value */
+    pub fn field_value(&self, name: &str) -> Option<String> {
+        let login = self.kind == RecordKind::Login;
+        let value = match name.to_lowercase().as_str() {
+            "id" => Some(self.id.clone()),
+            "entry" | "name" => Some(self.entry.clone()),
+            "username" | "user" if login => Some(self.username.clone()),
+            "password" | "pass" if login => Some(self.password.clone()),
+            "email" if login => self.email.clone(),
+            "note" if login => self.note.clone(),
+            "folder" => self.folder.clone(),
+            "tags" => Some(self.tags.join(",")),
+            x => match self.details.get(x) {
+                Some(v) => Some(v.clone()),
+                None => self.fields.iter().find(|f| f.name.eq_ignore_ascii_case(name)).map(|f| f.value.clone()),
+            },
+        };
+        value.filter(|x| !x.is_empty())
+    }
+
+    /* Used by imports when the id is already taken */
+    pub fn renew_id(&mut self) {
+        self.id = new_id();
+    }
+
+    /* Used by imports, replaces all custom fields at once */
+    pub fn set_fields(&mut self, fields: Vec<CustomField>) {
+        self.fields = fields;
+    }
+
+    pub fn otp(&self) -> Option<&OtpConfig> {
+        self.otp.as_ref()
+    }
+
+    /* None removes the secret */
+    pub fn set_otp(&mut self, otp: Option<OtpConfig>) {
+        self.otp = otp;
+        self.updated_at = Some(Utc::now());
+    }
+
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    pub fn details(&self) -> &BTreeMap<String, String> {
+        &self.details
+    }
+
+    /* Values have been checked with kinds::validate */
+    pub fn set_kind(&mut self, kind: RecordKind, details: BTreeMap<String, String>) {
+        if self.kind != kind || self.details != details {
+            self.updated_at = Some(Utc::now());
+        }
+        self.kind = kind;
+        self.details = details;
+    }
+
+    pub fn rotate_days(&self) -> Option<u32> {
+        self.rotate_days
+    }
+
+    pub fn set_rotation(&mut self, days: Option<u32>) {
+        self.rotate_days = days;
+        self.updated_at = Some(Utc::now());
+    }
+
+    pub fn urls(&self) -> &[UrlRule] {
+        &self.urls
+    }
+
+    /* Only logins have urls, adding one that is already there changes its rule */
+    pub fn add_url(&mut self, url: UrlRule) -> Result<(), String> {
+        if self.kind != RecordKind::Login {
+            return Err(format!("'{}' is a {}, only logins have urls", self.entry, self.kind.name()));
+        }
+
+        match self.urls.iter().position(|x| x.url == url.url) {
+            Some(idx) => self.urls[idx] = url,
+            None => self.urls.push(url),
+        }
+        self.updated_at = Some(Utc::now());
+        Ok(())
+    }
+
+    /* `url` as given to --url-rm, compared after the same normalization as --url */
+    pub fn remove_url(&mut self, url: &str) -> bool {
+        let url = UrlRule::parse(url).map(|x| x.url).unwrap_or(url.to_string());
+        let count = self.urls.len();
+        self.urls.retain(|x| x.url != url);
+
+        if self.urls.len() != count {
+            self.updated_at = Some(Utc::now());
+        }
+        self.urls.len() != count
+    }
+
+    /* Used by imports, replaces all urls at once */
+    pub fn set_urls(&mut self, urls: Vec<UrlRule>) {
+        self.urls = urls;
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /* Adds or replaces (same name) an attachment, the replaced blob is left for the sweep */
+    pub fn attach(&mut self, attachment: Attachment) -> bool {
+        self.updated_at = Some(Utc::now());
+        match self.attachments.iter().position(|x| x.name == attachment.name) {
+            Some(idx) => { self.attachments[idx] = attachment; true },
+            None => { self.attachments.push(attachment); false },
+        }
+    }
+
+    /* Copy written by a json export: attachment keys never leave the vault (extract the files instead),
+     * otp secrets only when asked for */
+    pub fn exported(&self, otp_secrets: bool) -> Record {
+        let mut record = self.clone();
+        record.attachments.clear();
+        if !otp_secrets {
+            record.otp = None;
+        }
+        record
+    }
+
+    /* Hotp code for the stored counter, the counter moves on so the code is never handed out twice */
+    pub fn next_hotp(&mut self) -> Result<String, String> {
+        let otp = self.otp.as_mut().ok_or(format!("'{}' has no otp secret", self.entry))?;
+        let code = otp.code(otp.counter)?;
+        otp.counter += 1;
+        Ok(code)
+    }
+}
+
+impl FieldKind {
+    fn parse(kind: &str) -> Result<Self, String> {
+        match kind.trim().to_lowercase().as_str() {
+            "text" => Ok(FieldKind::Text),
+            "hidden" | "secret" => Ok(FieldKind::Hidden),
+            "url" => Ok(FieldKind::Url),
+            "number" => Ok(FieldKind::Number),
+            x => Err(format!("Unknown field kind '{}', expected text, hidden, url or number", x)),
+        }
+    }
+
+    fn validate(&self, value: &str) -> Result<(), String> {
+        match self {
+            FieldKind::Number if value.trim().parse::<f64>().is_err() => Err(format!("'{}' is not a number", value)),
+            FieldKind::Url if !value.split_once("://").is_some_and(|(scheme, rest)| !scheme.is_empty() && !rest.is_empty()) =>
+                Err(format!("'{}' is not a url (scheme://...)", value)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CustomField {
+    fn display(&self) -> String {
+        format!("{}: {}", self.name, self.shown())
+    }
+
+    /* The value, or the mask for a hidden field that wasn't revealed */
+    fn shown(&self) -> String {
+        match self.kind {
+            FieldKind::Hidden if !output::reveals(&self.name) => output::MASK.to_string(),
+            _ => self.value.clone(),
+        }
+    }
+}
+
+/* "/infra//aws/" -> "infra/aws", the top level is None */
+pub fn normalize_folder(folder: &str) -> Option<String> {
+    let parts: Vec<&str> = folder.split('/')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    if parts.is_empty() { None } else { Some(parts.join("/")) }
+}
+
+impl SealedRecord {
+    fn mac(salt: &str, data: &str, key: &ProfileKey) -> HmacSha256 {
+        let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(&key.subkey("hmac")).expect("[!] Error: Creating hmac");
+        mac.update(salt.as_bytes());
+        mac.update(data.as_bytes());
+        mac
+    }
+
+    /* constant time comparison */
+    fn verify(&self, key: &ProfileKey) -> bool {
+        match decode(&self.hmac) {
+            Ok(tag) => SealedRecord::mac(&self.salt, &self.data, key).verify_slice(&tag).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn open(&self, key: &ProfileKey) -> Result<Record, String> {
+        let plaintext = open_message(&key.record(&self.salt), &self.data)?;
+        serde_json::from_str(&plaintext).map_err(|e| format!("[!] Error: {e}"))
+    }
+}
+
+impl LegacyRecord {
+    fn decrypt_record(&self, key: &[u8]) -> Result<Record, String> {
+        let nonce = decode(&self.salt).map_err(|e| format!("[!] Error: {e}"))?;
+        let decrypt_optional = |field: &Option<String>| {
+            field.as_ref().map(|x| decrypt(key, x, &nonce)).transpose()
+        };
+
+        Ok(Record {
+            id: new_id(),
+            entry: decrypt(key, &self.entry, &nonce)?,
+            username: decrypt(key, &self.username, &nonce)?,
+            password: decrypt(key, &self.password, &nonce)?,
+            email: decrypt_optional(&self.email)?,
+            note: decrypt_optional(&self.note)?,
+            created_at: None,
+            updated_at: None,
+            password_changed_at: None,
+            history: Vec::new(),
+            tags: Vec::new(),
+            folder: None,
+            fields: Vec::new(),
+            otp: None,
+            attachments: Vec::new(),
+            kind: RecordKind::Login,
+            details: BTreeMap::new(),
+            urls: Vec::new(),
+            rotate_days: None,
+        })
+    }
+
+    /* hmac as rsafe <= 1.8.2 computed it: keyed with salt[..12] + master + salt[12..]
+     * over the salt bytes and every plaintext field */
+    fn verify(&self, record: &Record, master: &str) -> bool {
+        let mut key_byte = String::new();
+        key_byte.push_str(&self.salt[..12]);
+        key_byte.push_str(master);
+        key_byte.push_str(&self.salt[12..]);
+
+        let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(key_byte.as_bytes()).expect("[!] Error: Creating hmac");
+        mac.update(&decode(&self.salt).unwrap_or_default());
+        mac.update(record.entry.as_bytes());
+        mac.update(record.username.as_bytes());
+        mac.update(record.password.as_bytes());
+        mac.update(record.email.clone().unwrap_or_default().as_bytes());
+        mac.update(record.note.clone().unwrap_or_default().as_bytes());
+
+        match decode(&self.hmac) {
+            Ok(tag) => mac.verify_slice(&tag).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl StoredRecord {
+    fn hmac(&self) -> &str {
+        match self {
+            StoredRecord::Sealed(x) => &x.hmac,
+            StoredRecord::Legacy(x) => &x.hmac,
+        }
+    }
+
+    /* hex of the 12 bytes every layout slices the key material and nonce from */
+    fn has_valid_salt(&self) -> bool {
+        let salt = match self {
+            StoredRecord::Sealed(x) => &x.salt,
+            StoredRecord::Legacy(x) => &x.salt,
+        };
+        salt.len() == 24 && decode(salt).is_ok()
+    }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VaultError::WrongPassword => write!(f, "Incorrect Password"),
+            VaultError::Integrity { profile, record: Some(idx) } =>
+                write!(f, "Integrity failure: record #{} of profile '{}' was modified", idx, profile),
+            VaultError::Integrity { profile, record: None } =>
+                write!(f, "Integrity failure: records of profile '{}' were added, removed or reordered", profile),
+            VaultError::FileIntegrity =>
+                write!(f, "Integrity failure: a profile was added, removed or replaced by an older copy"),
+            VaultError::Other(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+impl From<String> for VaultError {
+    fn from(err: String) -> Self {
+        VaultError::Other(err)
+    }
+}
+
+impl DumpFile {
+    pub fn new() -> Self {
+        DumpFile {
+            version: migrate::FORMAT_VERSION, default: None, profiles: HashMap::new(), index: None, mac: None,
+            subkeys: Subkeys::Hkdf, vault_key: OnceLock::new(),
+        }
+    }
+
+    /* Adds an empty profile protected by `key`, sealed vaults and those with a file mac need any existing
+     * password as `index_key` */
+    pub fn add_profile(&mut self, profile: &str, key: &str, index_key: Option<&str>) -> Result<(), VaultError> {
+        if self.contains_profile(profile, index_key)? {
+            return Err(VaultError::Other(format!("Profile '{}' already exists!", profile)));
+        }
+
+        let mut stored = Profile { kdf: Some(KdfParams::default()), subkeys: Subkeys::Hkdf, ..Default::default() };
+
+        if !self.is_sealed() {
+            if self.mac.is_some() {
+                self.find_vault_key(index_key.unwrap_or_default())?;
+            }
+            self.profiles.insert(profile.to_string(), stored);
+            store(self, &[], key, profile)?;
+            return Ok(());
+        }
+
+        let mut open = self.open_index(index_key.unwrap_or_default())?;
+        let id = encode(random_bytes(16));
+
+        stored.wrap = Some(self.wrap_vault_key(&open.vault_key, key)?);
+        self.profiles.insert(id.clone(), stored);
+
+        open.index.names.insert(profile.to_string(), id.clone());
+        self.write_index(&open)?;
+        store(self, &[], key, &id)?;
+        Ok(())
+    }
+
+    /* Profiles of an unsealed file that don't hold the vault key, so the file mac can't cover them.
+     * Only files written before the file mac with several profiles have them, see cover_profiles */
+    pub fn uncovered_profiles(&self) -> Vec<String> {
+        if self.is_sealed() {
+            return Vec::new();
+        }
+
+        let mut names: Vec<String> = self.profiles.iter()
+            .filter(|(_, x)| x.wrap.is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /* Whether adding or removing a profile has to rewrite a mac or sealed index, which takes a password */
+    pub fn has_mac(&self) -> bool {
+        self.is_sealed() || self.mac.is_some()
+    }
+
+    /* Empty profiles from before key checks existed accept any password until one is set */
+    pub fn has_password(&self, profile: Option<&String>) -> Result<bool, String> {
+        if self.is_sealed() {
+            return Ok(true);
+        }
+
+        let profile = self.profile_name(profile)?;
+        let stored = &self.profiles[&profile];
+        Ok(stored.check.is_some() || !stored.records.is_empty())
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /* Profile names, reading them from a sealed vault needs any profile's master password */
+    pub fn profile_names(&self, index_key: Option<&str>) -> Result<Vec<String>, VaultError> {
+        if !self.is_sealed() {
+            return Ok(self.profiles.keys().cloned().collect());
+        }
+
+        let open = self.open_index(index_key.unwrap_or_default())?;
+        Ok(open.index.names.into_keys().collect())
+    }
+
+    pub fn contains_profile(&self, profile: &str, index_key: Option<&str>) -> Result<bool, VaultError> {
+        Ok(self.profile_names(index_key)?.iter().any(|x| x == profile))
+    }
+
+    pub fn set_default(&mut self, profile: &str, index_key: Option<&str>) -> Result<(), VaultError> {
+        if !self.contains_profile(profile, index_key)? {
+            return Err(VaultError::Other(format!("Profile '{}' doesn't exist", profile)));
+        }
+
+        if !self.is_sealed() {
+            self.default = Some(profile.to_string());
+            return Ok(());
+        }
+
+        let mut open = self.open_index(index_key.unwrap_or_default())?;
+        open.index.default = Some(profile.to_string());
+        self.write_index(&open)?;
+        Ok(())
+    }
+
+    pub fn default_profile(&self, index_key: Option<&str>) -> Result<Option<String>, VaultError> {
+        if !self.is_sealed() {
+            return Ok(self.default.clone());
+        }
+
+        Ok(self.open_index(index_key.unwrap_or_default())?.index.default)
+    }
+
+    pub fn remove_profile(&mut self, profile: &str, index_key: Option<&str>) -> Result<(), VaultError> {
+        if !self.is_sealed() {
+            if self.profiles.get(profile).is_some_and(|x| x.wrap.is_some()) {
+                self.find_vault_key(index_key.unwrap_or_default())?;
+            }
+            self.profiles.remove(profile);
+            if self.default.as_deref() == Some(profile) {
+                self.default = None;
+            }
+            return Ok(());
+        }
+
+        let mut open = self.open_index(index_key.unwrap_or_default())?;
+        if let Some(id) = open.index.names.remove(profile) {
+            open.index.attachments.remove(&id);
+            self.profiles.remove(&id);
+        }
+        if open.index.default.as_deref() == Some(profile) {
+            open.index.default = None;
+        }
+        self.write_index(&open)?;
+        Ok(())
+    }
+
+    /* Tries the password against every wrapped copy of the vault key */
+    fn open_index(&self, key: &str) -> Result<OpenIndex, VaultError> {
+        let sealed = self.index.as_ref().ok_or("Profile metadata isn't sealed".to_string())?;
+        let derived = sealed.kdf.derive(key)?;
+
+        /* each wrap is made with the subkeys of its own profile */
+        let vault_key = self.profiles.values()
+            .filter_map(|x| Some((x.subkeys, x.wrap.as_ref()?)))
+            .find_map(|(subkeys, wrap)| open_message(&subkeys.derive("wrap", &derived), wrap).ok())
+            .ok_or(VaultError::WrongPassword)?;
+
+        let index = self.read_index(&vault_key)?;
+        let _ = self.vault_key.set(vault_key.clone());
+        Ok(OpenIndex { index, vault_key })
+    }
+
+    fn read_index(&self, vault_key: &str) -> Result<Index, VaultError> {
+        let sealed = self.index.as_ref().ok_or("Profile metadata isn't sealed".to_string())?;
+        let index = open_message(&self.subkeys.derive("index", vault_key), &sealed.data)
+            .map_err(|_| VaultError::Integrity { profile: "index".to_string(), record: None })?;
+        Ok(serde_json::from_str(&index).map_err(|e| format!("{e}"))?)
+    }
+
+    fn write_index(&mut self, open: &OpenIndex) -> Result<(), String> {
+        let plaintext = serde_json::to_string(&open.index).map_err(|e| format!("{e}"))?;
+        let data = seal_message(&self.subkeys.derive("index", &open.vault_key), &plaintext)?;
+
+        match self.index.as_mut() {
+            Some(x) => x.data = data,
+            None => return Err("Profile metadata isn't sealed".to_string()),
+        }
+        Ok(())
+    }
+
+    /* New wraps always use hkdf, store() moves the profile to it in the same write */
+    fn wrap_vault_key(&self, vault_key: &str, key: &str) -> Result<String, String> {
+        let sealed = self.index.as_ref().ok_or("Profile metadata isn't sealed".to_string())?;
+        seal_message(&Subkeys::Hkdf.derive("wrap", &sealed.kdf.derive(key)?), vault_key)
+    }
+
+    /* (name, storage key) of --from <profile> or the default one, the two only differ when sealed */
+    fn resolve(&self, profile: Option<&String>, key: &str) -> Result<(String, String), VaultError> {
+        if !self.is_sealed() {
+            let name = self.profile_name(profile)?;
+            return Ok((name.clone(), name));
+        }
+
+        let open = self.open_index(key)?;
+        let name = match profile {
+            Some(x) => x.clone(),
+            None => open.index.default.clone()
+                .ok_or("No default profile is set, use --from <profile>".to_string())?,
+        };
+
+        match open.index.names.get(&name) {
+            Some(id) => Ok((name, id.clone())),
+            None => Err(VaultError::Other(format!("Profile '{}' doesn't exist", name))),
+        }
+    }
+
+    /* Older layouts are upgraded (after taking a backup) before they are parsed */
+    pub fn load_dumpfile(path: &str) -> Result<Self, String> {
+        let mut value = DumpFile::load_raw(path)?;
+        let version = migrate::version(&value);
+        migrate::check_version(version)?;
+
+        if version < migrate::FORMAT_VERSION {
+            let backup = migrate::backup(path, version)?;
+            migrate::migrate(&mut value)?;
+
+            let mut dump_file: DumpFile = serde_json::from_value(value).map_err(|err| format!("{err}"))?;
+            dump_file.dump_dumpfile(path)?;
+            say!("[+] Vault was upgraded from format v{} to v{} (backup: {})", version, migrate::FORMAT_VERSION, backup);
+            return Ok(dump_file);
+        }
+
+        serde_json::from_value(value).map_err(|err| format!("{err}"))
+    }
+
+    /* Migrates in memory only, for files that must not be rewritten (backups) */
+    pub fn load_snapshot(path: &str) -> Result<Self, String> {
+        let mut value = DumpFile::load_raw(path)?;
+        migrate::migrate(&mut value)?;
+        serde_json::from_value(value).map_err(|err| format!("{err}"))
+    }
+
+    /* The file as plain json, without any migration applied */
+    pub fn load_raw(path: &str) -> Result<serde_json::Value, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("{e}"))?;
+
+        if data.is_empty() {
+            return Err("Error: File is empty!".to_string());
+        }
+
+        serde_json::from_str(&data).map_err(|err| format!("{err}"))
+    }
+
+    /* --from <profile> or the default one */
+    pub fn profile_name(&self, profile: Option<&String>) -> Result<String, String> {
+        let name = match profile {
+            Some(x) => x.clone(),
+            None => self.default.clone()
+                .ok_or("No default profile is set, use --from <profile>".to_string())?,
+        };
+
+        if !self.profiles.contains_key(&name) {
+            return Err(format!("Profile '{}' doesn't exist", name));
+        }
+
+        Ok(name)
+    }
+
+    /* Key every other key of the profile is made from: argon2id output if the profile has params,
+     * the raw master password for profiles that were never upgraded (store() upgrades them) */
+    fn master_key(&self, profile: &str, key: &str) -> Result<ProfileKey, String> {
+        let stored = self.profiles.get(profile);
+        let subkeys = stored.map(|x| x.subkeys).unwrap_or_default();
+
+        match stored.and_then(|x| x.kdf.as_ref()) {
+            Some(params) => Ok(ProfileKey { key: params.derive(key)?, subkeys }),
+            None => Ok(ProfileKey { key: key.to_string(), subkeys }),
+        }
+    }
+
+    pub fn dump_dumpfile(&mut self, path: &str) -> Result<(), String> {
+        self.update_mac()?;
+        let encoded = serde_json::to_string_pretty(self).map_err(|e| format!("{e}"))?;
+        storage::write_atomic(path, encoded.as_bytes())?;
+
+        // the vault is already safely written at this point, a failed backup or cleanup only warns
+        if let Err(err) = backups::snapshot(path) {
+            warn!("Backup failed: {err}");
+        }
+        if let Some(referenced) = self.referenced_blobs(path)
+            && let Err(err) = blobs::sweep(path, &referenced) {
+            warn!("Removing unused attachments failed: {err}");
+        }
+        Ok(())
+    }
+
+    /* Only a write that knows the vault key can change what the mac covers,
+     * it also moves the sealed index and the mac to hkdf subkeys */
+    fn update_mac(&mut self) -> Result<(), String> {
+        let vault_key = match self.vault_key.get() {
+            Some(x) => x.clone(),
+            None => return Ok(()),
+        };
+
+        if self.subkeys != Subkeys::Hkdf && self.is_sealed() {
+            let index = self.read_index(&vault_key).map_err(|e| e.to_string())?;
+            self.subkeys = Subkeys::Hkdf;
+            self.write_index(&OpenIndex { index, vault_key: vault_key.clone() })?;
+        }
+        self.subkeys = Subkeys::Hkdf;
+
+        self.mac = Some(encode(self.file_mac(&vault_key).finalize().into_bytes()));
+        Ok(())
+    }
+
+    /* Over the sealed index and every profile holding the vault key, pending ones are left out so they
+     * can still be stored by their own password alone. A manifest covers the records and the wrap
+     * of its profile, a sealed profile is covered by its blob, and both by their attachment ids */
+    fn file_mac(&self, vault_key: &str) -> HmacSha256 {
+        let mut names: Vec<&String> = self.profiles.iter()
+            .filter(|(_, x)| x.wrap.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(&self.subkeys.derive("file", vault_key)).expect("[!] Error: Creating hmac");
+        mac.update(&(names.len() as u64).to_le_bytes());
+        for name in names {
+            let stored = &self.profiles[name];
+            mac.update(name.as_bytes());
+            mac.update(&[0u8]);
+            mac.update(stored.mac.as_deref().unwrap_or_default().as_bytes());
+            if let Some(blob) = &stored.blob {
+                mac.update(blob.as_bytes());
+            }
+            for id in &stored.attachments {
+                mac.update(&[0u8]);
+                mac.update(id.as_bytes());
+            }
+        }
+        if let Some(index) = &self.index {
+            mac.update(index.data.as_bytes());
+        }
+        mac
+    }
+
+    /* Checks the file mac once `id` is unlocked, Ok(false) when the profile doesn't hold the vault key yet */
+    fn verify_mac(&self, id: &str, master_key: &ProfileKey) -> Result<bool, VaultError> {
+        let vault_key = match self.is_sealed() {
+            true => self.vault_key.get().ok_or("Profile index isn't open".to_string())?.clone(),
+            false => match self.profiles.get(id).and_then(|x| x.wrap.as_ref()) {
+                Some(wrap) => open_message(&master_key.subkey("wrap"), wrap).map_err(|_| VaultError::FileIntegrity)?,
+                None => return Ok(false),
+            },
+        };
+
+        let valid = match self.mac.as_ref().map(decode) {
+            Some(Ok(tag)) => self.file_mac(&vault_key).verify_slice(&tag).is_ok(),
+            _ => false,
+        };
+        if !valid {
+            return Err(VaultError::FileIntegrity);
+        }
+
+        let _ = self.vault_key.set(vault_key);
+        Ok(true)
+    }
+
+    /* The vault key of an unsealed file from any master password that opens a profile holding it */
+    fn find_vault_key(&self, password: &str) -> Result<(), VaultError> {
+        let mut names: Vec<&String> = self.profiles.iter()
+            .filter(|(_, x)| x.wrap.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort_by_key(|x| (self.default.as_ref() != Some(*x), x.to_string()));
+
+        for name in names {
+            let master_key = self.master_key(name, password)?;
+            let check = self.profiles[name].check.as_ref();
+            if check.is_some_and(|x| verify_check(x, &master_key)) {
+                self.verify_mac(name, &master_key)?;
+                return Ok(());
+            }
+        }
+        Err(VaultError::WrongPassword)
+    }
+
+    /* Every blob the vault, dump.json.bak or a kept backup still uses, so restoring one of them
+     * never leaves an attachment behind. None when one of them can't be read, nothing is removed then */
+    fn referenced_blobs(&self, path: &str) -> Option<HashSet<String>> {
+        let vault_key = self.vault_key.get().cloned();
+        let mut referenced = self.attachment_ids(vault_key.as_deref())?;
+
+        let mut others: Vec<String> = backups::list(path).ok()?.iter()
+            .map(|x| x.path.to_string_lossy().to_string())
+            .collect();
+        let bak = format!("{}.bak", path);
+        if fs::metadata(&bak).is_ok() {
+            others.push(bak);
+        }
+
+        for other in others {
+            let file = DumpFile::load_snapshot(&other).ok()?;
+            referenced.extend(file.attachment_ids(vault_key.as_deref())?);
+        }
+        Some(referenced)
+    }
+
+    /* Blobs used by some profile of this file, None if it is sealed under another (or no known) vault key */
+    fn attachment_ids(&self, vault_key: Option<&str>) -> Option<HashSet<String>> {
+        let mut ids: HashSet<String> = self.profiles.values()
+            .flat_map(|x| x.attachments.iter().cloned())
+            .collect();
+
+        if self.is_sealed() {
+            let index = self.read_index(vault_key?).ok()?;
+            ids.extend(index.attachments.into_values().flatten());
+        }
+        Some(ids)
+    }
+
+}
+
+impl Default for DumpFile {
+    fn default() -> Self {
+        DumpFile::new()
+    }
+}
+
+impl TabledData {
+    fn new(data: Record) -> Self {
+        let null = String::from("null");
+        TabledData {
+            id: data.id(),
+            entry: data.entry(), username: data.username(), password: shown_password(&data),
+            email: data.email().unwrap_or(null.clone()), note: data.note().unwrap_or(null.clone()),
+            folder: data.folder().unwrap_or("/".to_string()), tags: data.tags().join(", "),
+            fields: extras(&data),
+        }
+    }
+}
+
+/* The password of a login, masked unless --reveal or --reveal-field password */
+fn shown_password(data: &Record) -> String {
+    match output::reveals("password") {
+        true => data.password(),
+        false => output::MASK.to_string(),
+    }
+}
+
+/* The "fields" column: urls, custom fields, otp settings and attachments */
+fn extras(data: &Record) -> String {
+    data.urls().iter().map(|x| x.display())
+        .chain(data.fields().iter().map(|x| x.display()))
+        .chain(data.otp().map(|x| x.summary()))
+        .chain(data.attachments().iter().map(|x| format!("file: {} ({} bytes)", x.name, x.size)))
+        .collect::<Vec<_>>().join("\n")
+}
+
+/* What --format json prints for a record, masked like the tables.
+ * Attachment keys, otp secrets and the password history are left out */
+#[derive(Serialize)]
+struct RecordView {
+    id: String,
+    kind: RecordKind,
+    entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<Option<String>>,     // null for a login without one, left out for other kinds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<Option<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    details: BTreeMap<String, String>,
+    folder: Option<String>,
+    tags: Vec<String>,
+    fields: Vec<CustomField>,
+    urls: Vec<UrlRule>,
+    otp: Option<String>,
+    attachments: Vec<AttachmentView>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    password_changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct AttachmentView {
+    name: String,
+    size: u64,
+    added_at: Option<DateTime<Utc>>,
+}
+
+impl RecordView {
+    fn new(data: &Record) -> Self {
+        let login = data.kind == RecordKind::Login;
+        let details = data.kind.schema().iter()
+            .filter_map(|field| Some((field.name.to_string(), kinds::display(field, data.details.get(field.name)?))))
+            .collect();
+
+        let fields = data.fields.iter()
+            .map(|x| CustomField { value: x.shown(), ..x.clone() })
+            .collect();
+
+        RecordView {
+            id: data.id(),
+            kind: data.kind,
+            entry: data.entry(),
+            username: Some(data.username()).filter(|_| login),
+            password: Some(shown_password(data)).filter(|_| login),
+            email: Some(data.email()).filter(|_| login),
+            note: Some(data.note()).filter(|_| login),
+            details,
+            folder: data.folder(),
+            tags: data.tags(),
+            fields,
+            urls: data.urls.clone(),
+            otp: data.otp().map(|x| x.summary()),
+            attachments: data.attachments.iter()
+                .map(|x| AttachmentView { name: x.name.clone(), size: x.size, added_at: x.added_at })
+                .collect(),
+            created_at: data.created_at,
+            updated_at: data.updated_at,
+            password_changed_at: data.password_changed_at,
+        }
+    }
+}
+
+/* --format plain: one tab separated line per record, id kind entry then the kind's own values */
+fn plain_line(data: &Record) -> String {
+    let mut values = vec![data.id(), data.kind.name().to_string(), data.entry()];
+    match data.kind {
+        RecordKind::Login => values.extend([
+            data.username(), shown_password(data), data.email().unwrap_or_default(), data.note().unwrap_or_default()
+        ]),
+        kind => values.extend(kind.schema().iter().map(|field| match data.details.get(field.name) {
+            Some(value) => kinds::display(field, value),
+            None => String::new(),
+        })),
+    }
+
+    values.iter()
+        .map(|x| x.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n"))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/* Secrets are masked unless they were revealed, see output::reveals */
+pub fn record_fmt(data: RecordPrint) {
+    let records = match data {
+        RecordPrint::VECTOR(records) => records,
+        RecordPrint::RECORD(record) => vec![record],
+    };
+
+    match output::format() {
+        output::Format::Json => return output::json(records.iter().map(RecordView::new).collect::<Vec<_>>()),
+        output::Format::Plain => {
+            for record in &records {
+                println!("{}", plain_line(record));
+            }
+            return;
+        },
+        output::Format::Table => {},
+    }
+
+    /* one table per kind, each with the columns of its schema */
+    let mut kinds: Vec<RecordKind> = records.iter().map(|x| x.kind()).collect();
+    kinds.sort();
+    kinds.dedup();
+
+    for kind in kinds {
+        let group = records.iter().filter(|x| x.kind() == kind).cloned();
+
+        let mut table = match kind {
+            RecordKind::Login => Table::new(group.map(TabledData::new)),
+            _ => kind_table(kind, group),
+        };
+
+        if records.iter().any(|x| x.kind() != kind) {
+            say!("[#] {}:", kind.name());
+        }
+
+        table.with(Style::rounded());
+        use tabled::settings::Modify;
+        table
+            .with(Modify::new(Columns::new(..)).with(Width::wrap(WRAP_WIDTH).keep_words(true)))
+            .with(Modify::new(Columns::new(..)).with(Alignment::left()));
+
+        println!("{}", table);
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct DumpFile {
-    pub default: Option<String>,
-    pub profiles: HashMap<String, Vec<Record>>
+fn kind_table(kind: RecordKind, records: impl Iterator<Item = Record>) -> Table {
+    let schema = kind.schema();
+    let mut builder = Builder::default();
+
+    let mut header = vec!["id", "entry"];
+    header.extend(schema.iter().map(|x| x.label));
+    header.extend(["folder", "tags", "fields"]);
+    builder.push_record(header);
+
+    for record in records {
+        let mut row = vec![record.id(), record.entry()];
+        row.extend(schema.iter().map(|field| match record.details().get(field.name) {
+            Some(value) => kinds::display(field, value),
+            None => "null".to_string(),
+        }));
+        row.extend([record.folder().unwrap_or("/".to_string()), record.tags().join(", "), extras(&record)]);
+        builder.push_record(row);
+    }
+
+    builder.build()
 }
 
-pub enum RecordPrint {
-    VECTOR(Vec<Record>),
-    RECORD(Record),
+#[derive(Default, Serialize)]
+struct FolderNode {
+    folders: BTreeMap<String, FolderNode>,
+    records: Vec<String>,
 }
 
-type HmacSha256 = Hmac<Sha256>;
-const WRAP_WIDTH: usize = 40;
+/* Prints the folders as a tree with the records inside them */
+pub fn folder_tree(records: &[Record]) {
+    let mut root = FolderNode::default();
 
-impl Record {
-    pub fn new(data: &[String], key: &str) -> Self {
-        if data.len() != 5 {
-            panic!("[!] Error: new function call requires 5");
+    for record in records {
+        let mut node = &mut root;
+        for part in record.folder().unwrap_or_default().split('/').filter(|x| !x.is_empty()) {
+            node = node.folders.entry(part.to_string()).or_default();
         }
+        node.records.push(format!("{} ({})", record.entry(), record.username()));
+    }
 
-        let bytes: Vec<u8> = (0..12).map(|_| { random::<u8>() }).collect();
-        let salt = encode(&bytes);
+    match output::format() {
+        output::Format::Json => output::json(&root),
+        output::Format::Plain => print_paths(&root, ""),
+        output::Format::Table => {
+            println!("/");
+            print_folder(&root, "");
+        },
+    }
+}
 
-        /* key = hash(salt[..12] + key + salt[12..]) */
-        let mut key_byte = String::new();
-        key_byte.push_str(&salt[..12]);
-        key_byte.push_str(key);
-        key_byte.push_str(&salt[12..]);
+/* "infra/aws/console (bob)", one line per record */
+fn print_paths(node: &FolderNode, path: &str) {
+    for record in &node.records {
+        println!("{}{}", path, record);
+    }
+    for (name, child) in &node.folders {
+        print_paths(child, &format!("{}{}/", path, name));
+    }
+}
 
-        let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(key_byte.as_bytes()).expect("[!] Error: Creating hmac");
-        mac.update(&bytes);
-        for x in data {
-            mac.update(x.as_bytes());
-        }
+fn print_folder(node: &FolderNode, prefix: &str) {
+    let mut lines: Vec<(String, Option<&FolderNode>)> = node.folders.iter()
+        .map(|(name, child)| (format!("{}/", name), Some(child)))
+        .collect();
 
-        Record {
-            salt,
-            entry: data[0].clone(),
-            username: data[1].clone(),
-            password: data[2].clone(),
-            email: if data[3].is_empty() { None } else { Some(data[3].clone()) },
-            note: if data[4].is_empty() { None } else { Some(data[4].clone()) },
-            hmac: encode(mac.finalize().into_bytes()),
+    let mut records = node.records.clone();
+    records.sort();
+    lines.extend(records.into_iter().map(|x| (x, None)));
+
+    for (idx, (line, child)) in lines.iter().enumerate() {
+        let last = idx + 1 == lines.len();
+        println!("{}{} {}", prefix, if last { "└──" } else { "├──" }, line);
+
+        if let Some(child) = child {
+            print_folder(child, &format!("{}{}", prefix, if last { "    " } else { "│   " }));
         }
     }
+}
 
-    fn encrypt_record(&self, key: &[u8]) -> Self {
-        let nonce = decode(&self.salt).unwrap();
-        let (mut email, mut note) = (None, None);
+pub fn load(path: &str, key: &str, profile: Option<&String>) -> Result<Option<Vec<Record>>, VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+    let unlocked = unlock(&dump_file, key, profile)?;
 
-        let entry = match encrypt(key, &self.entry, &nonce) {
-            Ok(x) => x,
-            Err(x) => panic!("[!] Error encrypting: {x}"),
-        };
+    /* re-encrypt old profiles with a nonce per record as soon as we know the key */
+    if unlocked.outdated {
+        store(&mut dump_file, &unlocked.records, key, &unlocked.id)?;
+        dump_file.dump_dumpfile(path)?;
+    }
 
-        let username = match encrypt(key, &self.username, &nonce) {
-            Ok(x) => x,
-            Err(x) => panic!("[!] Error encrypting: {x}"),
-        };
+    if unlocked.legacy {
+        say!("[+] Profile '{}' was migrated to the new record layout", unlocked.name);
+    }
+    if dump_file.uncovered_profiles().contains(&unlocked.id) {
+        warn!("Profile '{}' isn't covered by the vault mac yet, `rsafe migrate` adds it", unlocked.name);
+    }
 
-        let password = match encrypt(key, &self.password, &nonce) {
-            Ok(x) => x,
-            Err(x) => panic!("[!] Error encrypting: {x}"),
-        };
+    Ok(Some(unlocked.records))
+}
 
-        if let Some(_email) = &self.email {
-            email = match encrypt(key, &_email, &nonce) {
-                Ok(x) => Some(x),
-                Err(x) => panic!("[!] Error encrypting: {x}"),
-            };
-        }
+/* Rotation policy of a profile in days, read without unlocking it */
+pub fn profile_rotation(path: &str, key: &str, profile: Option<&String>) -> Result<Option<u32>, VaultError> {
+    let dump_file = DumpFile::load_dumpfile(path)?;
+    let (_, id) = dump_file.resolve(profile, key)?;
+    Ok(dump_file.profiles.get(&id).and_then(|x| x.rotate_days))
+}
 
-        if let Some(_note) = &self.note {
-            note = match encrypt(key, &_note, &nonce) {
-                Ok(x) => Some(x),
-                Err(x) => panic!("[!] Error encrypting: {x}"),
-            };
-        }
+/* None removes the policy, the password is checked first */
+pub fn set_profile_rotation(path: &str, key: &str, profile: Option<&String>, days: Option<u32>) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+    let unlocked = unlock(&dump_file, key, profile)?;
 
-        Record {
-            salt: self.salt.clone(),
-            entry,
-            username,
-            password,
-            email,
-            note,
-            hmac: self.hmac.clone(), // same hmac
-        }
+    if let Some(stored) = dump_file.profiles.get_mut(&unlocked.id) {
+        stored.rotate_days = days;
+    }
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
+}
+
+/* Decrypts a profile of an already loaded file without writing anything back (backups, diffs) */
+pub fn open_records(dump_file: &DumpFile, key: &str, profile: Option<&String>) -> Result<Vec<Record>, VaultError> {
+    Ok(unlock(dump_file, key, profile)?.records)
+}
+
+struct Unlocked {
+    name: String,
+    id: String,
+    records: Vec<Record>,
+    legacy: bool,       // had records in the old layout
+    outdated: bool,     // has to be stored again to get the current layout, hmacs and key check
+}
+
+fn unlock(dump_file: &DumpFile, key: &str, profile: Option<&String>) -> Result<Unlocked, VaultError> {
+    let (name, profile) = dump_file.resolve(profile, key)?;
+    let master_key = dump_file.master_key(&profile, key)?;
+
+    let stored = dump_file.profiles.get(&profile).cloned().unwrap_or_default();
+    let records = stored.records;
+
+    if let Some(check) = &stored.check
+        && !verify_check(check, &master_key) {
+        return Err(VaultError::WrongPassword);
     }
 
-    fn decrypt_record(&self, key: &[u8]) -> Result<Self,String> {
-        let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(key).expect("[!] Error: Creating hmac");
-        let nonce = decode(&self.salt).unwrap();
-        let (mut email, mut note) = (None, None);
+    if let Some(blob) = &stored.blob {
+        let mut records = open_blob(blob, &profile, &master_key)
+            .ok_or(VaultError::Integrity { profile: name.clone(), record: None })?;
+        dump_file.verify_mac(&profile, &master_key)?;
+        /* blob ids written in plaintext before they moved into the index */
+        let outdated = assign_ids(&mut records) || !stored.attachments.is_empty()
+            || stored.kdf.is_none() || stored.subkeys != Subkeys::Hkdf;
 
-        let entry = decrypt(key, &self.entry, &nonce)?;
+        return Ok(Unlocked { name, id: profile, records, legacy: false, outdated });
+    }
 
-        let username = match decrypt(key, &self.username, &nonce) {
-            Ok(x) => {
-                mac.update(x.as_bytes());
-                x
-            },
-            Err(x) => return Err(x),
-        };
+    /* Decrypt and verify the records */
+    let mut decrypted_records: Vec<Record> = Vec::new();
+    let mut legacy = false;
 
-        let password = match decrypt(key, &self.password, &nonce) {
-            Ok(x) => {
-                mac.update(x.as_bytes());
-                x
+    for (idx, record) in records.iter().enumerate() {
+        let tampered = VaultError::Integrity { profile: name.clone(), record: Some(idx + 1) };
+
+        if !record.has_valid_salt() {
+            return Err(tampered);
+        }
+
+        /* sealed records are authenticated before anything is decrypted */
+        let decrypted_data = match record {
+            StoredRecord::Sealed(x) => match x.verify(&master_key) {
+                true => x.open(&master_key).map(|data| (data, true)),
+                false => Err("[!] Error: Record hmac doesn't match".to_string()),
+            },
+            StoredRecord::Legacy(x) => {
+                legacy = true;
+                x.decrypt_record(&Subkeys::Sha256.record(&x.salt, &master_key.key)).map(|data| {
+                    let valid = x.verify(&data, key);
+                    (data, valid)
+                })
             },
-            Err(x) => return Err(x),
         };
 
-        if let Some(_email) = &self.email {
-            email = match decrypt(key, &_email, &nonce) {
-                Ok(x) => {
-                    mac.update(x.as_bytes());
-                    Some(x)
-                },
-                Err(x) => return Err(x),
-            };
+        match decrypted_data {
+            Ok((data, true)) => decrypted_records.push(data),
+            Ok((_, false)) => return Err(tampered),
+            /* the key is known to be right, so this record was changed */
+            Err(_) if idx > 0 || stored.check.is_some() => return Err(tampered),
+            Err(_) => return Err(VaultError::WrongPassword),
         }
+    }
 
-        if let Some(_note) = &self.note {
-            note = match decrypt(key, &_note, &nonce) {
-                Ok(x) => {
-                    mac.update(x.as_bytes());
-                    Some(x)
-                },
-                Err(x) => return Err(x),
-            };
-        }
+    let sealed = records.iter().any(|x| matches!(x, StoredRecord::Sealed(_)));
+    let valid = match &stored.mac {
+        Some(mac) => verify_manifest(&profile, &records, stored.wrap.as_ref(), &master_key, mac),
+        None => !sealed,        // profiles written before manifests existed
+    };
+
+    if !valid {
+        return Err(VaultError::Integrity { profile: name, record: None });
+    }
+
+    /* the only profile of a file without a mac gets a vault key when stored, profiles of older files
+     * with several wait for cover_profiles (or --seal-metadata) */
+    let covered = dump_file.verify_mac(&profile, &master_key)?;
+    let new_ids = assign_ids(&mut decrypted_records);
+
+    Ok(Unlocked {
+        name,
+        id: profile,
+        records: decrypted_records,
+        legacy,
+        outdated: legacy || new_ids || stored.mac.is_none() || stored.check.is_none()
+            || (!covered && dump_file.mac.is_none() && dump_file.profiles.len() == 1)
+            || stored.kdf.is_none() || stored.subkeys != Subkeys::Hkdf,
+    })
+}
+
+/* Gives records from before ids existed a permanent one, true if any was missing */
+fn assign_ids(records: &mut [Record]) -> bool {
+    let mut assigned = false;
+
+    for record in records.iter_mut().filter(|x| x.id.is_empty()) {
+        record.id = new_id();
+        assigned = true;
+    }
+    assigned
+}
+
+fn new_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+pub fn dump(records: &[Record], path: &str, key: &str, profile: Option<&String>) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+    let (_, profile) = dump_file.resolve(profile, key)?;
+
+    store(&mut dump_file, records, key, &profile)?;
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
+}
+
+/* Re-encrypts the profile under `new_key` and new kdf parameters (a fresh salt with the same cost if None) */
+pub fn rekey(records: &[Record], path: &str, key: &str, new_key: &str, profile: Option<&String>, params: Option<KdfParams>) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+    let (_, profile) = dump_file.resolve(profile, key)?;
+
+    /* an unsealed profile rewraps its vault key under the new key in store */
+    let wrap = match dump_file.is_sealed() {
+        true => Some(dump_file.wrap_vault_key(&dump_file.open_index(key)?.vault_key, new_key)?),
+        false => {
+            dump_file.verify_mac(&profile, &dump_file.master_key(&profile, key)?)?;
+            None
+        },
+    };
+
+    let stored = dump_file.profiles.entry(profile.clone()).or_default();
+    stored.kdf = Some(match params {
+        Some(x) => x,
+        None => stored.kdf.as_ref().map(|x| x.resalt()).unwrap_or_default(),
+    });
+    stored.wrap = wrap;
+    /* the new wrap is made with hkdf, open_index has to read it that way */
+    stored.subkeys = Subkeys::Hkdf;
+
+    store(&mut dump_file, records, new_key, &profile)?;
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
+}
+
+fn store(dump_file: &mut DumpFile, records: &[Record], password: &str, profile: &str) -> Result<(), String> {
+    let attachments: Vec<String> = records.iter()
+        .flat_map(|x| x.attachments.iter().map(|x| x.id.clone()))
+        .collect();
+
+    /* the vault key is opened with the profile's current keys, before they are upgraded below */
+    let old_key = dump_file.master_key(profile, password)?;
+    let open = match dump_file.is_sealed() {
+        true => Some(dump_file.open_index(password).map_err(|e| e.to_string())?),
+        false => None,
+    };
+    let vault_key = match (&open, dump_file.vault_key.get()) {
+        (Some(open), _) => Some(open.vault_key.clone()),
+        (None, Some(x)) => Some(x.clone()),
+        (None, None) => match dump_file.profiles.get(profile).and_then(|x| x.wrap.as_ref()) {
+            Some(wrap) => Some(open_message(&old_key.subkey("wrap"), wrap).map_err(|_| "The vault key of this profile doesn't open".to_string())?),
+            /* a mac over only some profiles would leave the others open to being swapped,
+             * so a file with several profiles gets its vault key from cover_profiles */
+            None if dump_file.mac.is_none() && dump_file.profiles.len() == 1 => Some(encode(random_bytes(32))),
+            None => None,
+        },
+    };
 
-        if encode(mac.finalize().into_bytes()) == self.hmac {
-            panic!("[!] Hashes doesnt match! Tamparing Detected");
+    /* profiles from before argon2id or hkdf are moved to both by their next write */
+    let stored = dump_file.profiles.entry(profile.to_string()).or_default();
+    let upgrade = stored.kdf.is_none() || stored.subkeys != Subkeys::Hkdf;
+    if stored.kdf.is_none() {
+        stored.kdf = Some(KdfParams::default());
+    }
+    stored.subkeys = Subkeys::Hkdf;
+    let key = dump_file.master_key(profile, password)?;
+
+    if let Some(mut open) = open {
+        let blob = seal_blob(records, profile, &key)?;
+        let wrap = match upgrade && dump_file.profiles[profile].wrap.is_some() {
+            true => Some(dump_file.wrap_vault_key(&open.vault_key, password)?),
+            false => None,
+        };
+        let stored = dump_file.profiles.entry(profile.to_string()).or_default();
+        stored.blob = Some(blob);
+        stored.check = Some(seal_check(&key)?);
+        stored.attachments = Vec::new();
+        if wrap.is_some() {
+            stored.wrap = wrap;
         }
 
-        Ok(Record {
-            salt: self.salt.clone(),
-            entry,
-            username,
-            password,
-            email,
-            note,
-            hmac: self.hmac.clone(),
-        })
+        /* which blobs a profile uses would tell its records apart, so the ids are sealed too */
+        match attachments.is_empty() {
+            true => open.index.attachments.remove(profile),
+            false => open.index.attachments.insert(profile.to_string(), attachments),
+        };
+        return dump_file.write_index(&open);
     }
 
-    // entry username email note
-    pub fn entry(&self) -> String {
-        self.entry.clone()
+    let wrap = match vault_key {
+        Some(vault_key) => {
+            let wrap = seal_message(&key.subkey("wrap"), &vault_key)?;
+            let _ = dump_file.vault_key.set(vault_key);
+            Some(wrap)
+        },
+        None => None,
+    };
+
+    let mut encrypted_records: Vec<StoredRecord> = Vec::new();
+
+    for record in records {
+        encrypted_records.push(StoredRecord::Sealed(record.seal(&key)?));
     }
 
-    pub fn username(&self) -> String {
-        self.username.clone()
+    let mac = manifest(profile, &encrypted_records, wrap.as_ref(), &key).finalize().into_bytes();
+    let stored = dump_file.profiles.entry(profile.to_string()).or_default();
+    stored.mac = Some(encode(mac));
+    stored.wrap = wrap;
+    stored.check = Some(seal_check(&key)?);
+    stored.records = encrypted_records;
+    stored.attachments = attachments;
+    Ok(())
+}
+
+/* Renames a profile after unlocking it, the manifest covers the profile name */
+pub fn rename_profile(path: &str, key: &str, old_profile: &String, profile: &str) -> Result<(), VaultError> {
+    let records = load(path, key, Some(old_profile))?.unwrap_or_default();
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+
+    if dump_file.contains_profile(profile, Some(key))? {
+        return Err(VaultError::Other(format!("Profile '{}' already exists!", profile)));
     }
 
-    pub fn password(&self) -> String {
-        self.password.clone()
+    /* sealed blobs are bound to the id, only the index changes */
+    if dump_file.is_sealed() {
+        let mut open = dump_file.open_index(key)?;
+        if let Some(id) = open.index.names.remove(old_profile) {
+            open.index.names.insert(profile.to_string(), id);
+        }
+        if open.index.default.as_ref() == Some(old_profile) {
+            open.index.default = None;
+        }
+        dump_file.write_index(&open)?;
+        dump_file.dump_dumpfile(path)?;
+        return Ok(());
     }
 
-    pub fn email(&self) -> Option<String> {
-        self.email.clone()
+    if let Some(stored) = dump_file.profiles.remove(old_profile) {
+        dump_file.profiles.insert(profile.to_string(), stored);
     }
 
-    pub fn note(&self) -> Option<String> {
-        self.note.clone()
+    if dump_file.default.as_ref() == Some(old_profile) {
+        dump_file.default = None;
     }
+
+    store(&mut dump_file, &records, key, profile)?;
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
 }
 
-impl DumpFile {
-    pub fn load_dumpfile(path: &str) -> Result<Self, String> {
-        let data = fs::read_to_string(path)
-            .map_err(|e| format!("{e}"))?;
+/* Moves every profile behind random ids, `unlocked` is (name, master password, records) for all of them */
+pub fn seal_metadata(path: &str, unlocked: &[(String, String, Vec<Record>)]) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
 
-        if data.len() == 0 {
-            return Err(format!("Error: File is empty!"));
-        }
+    if dump_file.is_sealed() {
+        return Err(VaultError::Other("Profile metadata is already sealed".to_string()));
+    }
 
-        serde_json::from_str(&data).map_err(|err| format!("{err}"))
+    if unlocked.len() != dump_file.profiles.len() {
+        return Err(VaultError::Other("Every profile has to be unlocked to seal the metadata".to_string()));
     }
 
-    pub fn dump_dumpfile(&self, path: &str) -> Result<(), String> {
-        let encoded = serde_json::to_string_pretty(self).map_err(|e| format!("{e}"))?;
-        fs::write(path, encoded.as_bytes()).map_err(|e| format!("{e}"))
+    let vault_key = encode(random_bytes(32));
+    let _ = dump_file.vault_key.set(vault_key.clone());
+    let mut open = OpenIndex { index: Index { default: dump_file.default.take(), ..Default::default() }, vault_key };
+    let mut old_profiles = std::mem::take(&mut dump_file.profiles);
+    dump_file.index = Some(SealedIndex { kdf: KdfParams::default(), data: String::new() });
+
+    for (name, password, records) in unlocked {
+        let old = old_profiles.remove(name).ok_or(format!("Profile '{}' doesn't exist", name))?;
+        let id = encode(random_bytes(16));
+
+        let stored = Profile {
+            kdf: old.kdf,
+            rotate_days: old.rotate_days,
+            wrap: Some(dump_file.wrap_vault_key(&open.vault_key, password)?),
+            subkeys: Subkeys::Hkdf,
+            ..Default::default()
+        };
+
+        dump_file.profiles.insert(id.clone(), stored);
+        open.index.names.insert(name.clone(), id.clone());
+        dump_file.write_index(&open)?;
+
+        store(&mut dump_file, records, password, &id)?;
+        open.index = dump_file.read_index(&open.vault_key)?;
     }
 
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
 }
 
-impl TabledData {
-    fn new(data: Record) -> Self {
-        let null = String::from("null");
-        TabledData {
-            entry: data.entry(), username: data.username(), password: data.password(),
-            email: data.email().unwrap_or(null.clone()), note: data.note().unwrap_or(null.clone())
+/* Gives every profile of an unsealed file the vault key and writes the mac over all of them,
+ * `unlocked` as in seal_metadata. A file that already has a mac keeps its vault key */
+pub fn cover_profiles(path: &str, unlocked: &[(String, String, Vec<Record>)]) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+
+    if dump_file.is_sealed() {
+        return Err(VaultError::Other("Profile metadata is sealed, every profile is covered already".to_string()));
+    }
+    if unlocked.len() != dump_file.profiles.len() {
+        return Err(VaultError::Other("Every profile has to be unlocked to cover it with the vault mac".to_string()));
+    }
+
+    if dump_file.mac.is_some() {
+        if !unlocked.iter().any(|(_, password, _)| dump_file.find_vault_key(password).is_ok()) {
+            return Err(VaultError::FileIntegrity);
         }
+    } else {
+        let _ = dump_file.vault_key.set(encode(random_bytes(32)));
+    }
+
+    for (name, password, records) in unlocked {
+        store(&mut dump_file, records, password, name)?;
     }
+
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
 }
 
-pub fn record_fmt(data: RecordPrint) {
-    let mut tabled_data: Vec<TabledData> = Vec::new();
-    // fix the word wrapping and change it to when editing a password or deletion is done the
-    // password is encoded
-    match data {
-        RecordPrint::VECTOR(records) => {
-            for record in records {
-                tabled_data.push(TabledData::new(record));
-            }
-        },
-        RecordPrint::RECORD(record) => tabled_data.push(TabledData::new(record)),
+/* Puts the profile names back in plaintext, `unlocked` as in seal_metadata */
+pub fn unseal_metadata(path: &str, index_key: &str, unlocked: &[(String, String, Vec<Record>)]) -> Result<(), VaultError> {
+    let mut dump_file = DumpFile::load_dumpfile(path)?;
+    let open = dump_file.open_index(index_key)?;
+
+    if unlocked.len() != open.index.names.len() {
+        return Err(VaultError::Other("Every profile has to be unlocked to unseal the metadata".to_string()));
     }
 
-    let mut tabled_data = Table::new(tabled_data);
-    tabled_data.with(Style::rounded());
-    use tabled::settings::Modify;
-    tabled_data
-        .with(Modify::new(Columns::new(..)).with(Width::wrap(WRAP_WIDTH).keep_words(true)))
-        .with(Modify::new(Columns::new(..)).with(Alignment::left()));
+    let mut old_profiles = std::mem::take(&mut dump_file.profiles);
+    dump_file.index = None;
+    dump_file.default = open.index.default.clone();
+
+    for (name, password, records) in unlocked {
+        let old = open.index.names.get(name)
+            .and_then(|id| old_profiles.remove(id))
+            .ok_or(format!("Profile '{}' doesn't exist", name))?;
 
-    println!("{}", tabled_data);
+        dump_file.profiles.insert(name.clone(), Profile { kdf: old.kdf, rotate_days: old.rotate_days, ..Default::default() });
+        store(&mut dump_file, records, password, name)?;
+    }
+
+    dump_file.dump_dumpfile(path)?;
+    Ok(())
 }
 
-pub fn load(path: &str, key: &str, profile: Option<&String>) -> Result<Option<Vec<Record>>, String> {
-    let dump_file = DumpFile::load_dumpfile(path)?;
+fn seal_blob(records: &[Record], profile: &str, key: &ProfileKey) -> Result<String, String> {
+    let blob = Blob { profile: profile.to_string(), records: records.to_vec() };
+    let mut plaintext = serde_json::to_string(&blob).map_err(|e| format!("{e}"))?;
 
-    let records = match profile {
-        Some(profile_name) => dump_file.profiles.get(profile_name).cloned().unwrap(),
-        None => dump_file.profiles.get(&dump_file.default.unwrap()).cloned().unwrap()
-    };
+    /* pad with whitespace (still valid json) up to the next power of two */
+    let size = plaintext.len().next_power_of_two().max(BLOB_PADDING);
+    plaintext.push_str(&" ".repeat(size - plaintext.len()));
 
-    /* Decrypt the records */
-    let mut decrypted_records: Vec<Record> = Vec::new();
-    let mut new_key: String = String::new();
+    seal_message(&key.subkey("blob"), &plaintext)
+}
 
-    for record in records {
-        // key = hash(nonce[..12] + key + nonce[12..])
-        new_key.push_str(&record.salt[..12]);
-        new_key.push_str(key);
-        new_key.push_str(&record.salt[12..]);
-        let decrypted_data = record.decrypt_record(&hash256(&new_key))?;
-        decrypted_records.push(decrypted_data);
-        new_key.clear();
-    }
+/* None if the blob doesn't decrypt or belongs to another profile */
+fn open_blob(blob: &str, profile: &str, key: &ProfileKey) -> Option<Vec<Record>> {
+    let plaintext = open_message(&key.subkey("blob"), blob).ok()?;
+    let blob: Blob = serde_json::from_str(&plaintext).ok()?;
 
-    Ok(Some(decrypted_records))
+    (blob.profile == profile).then_some(blob.records)
 }
 
-pub fn dump(records: &[Record], path: &str, key: &str, profile: Option<&String>) -> Result<(), String>
-{
-    let mut encrypted_records: Vec<Record> = Vec::new();
-    let mut new_key: String = String::new();
+fn seal_check(key: &ProfileKey) -> Result<String, String> {
+    seal_message(&key.subkey("check"), KEY_CHECK)
+}
+
+fn verify_check(check: &str, key: &ProfileKey) -> bool {
+    open_message(&key.subkey("check"), check).is_ok_and(|x| x == KEY_CHECK)
+}
 
+/* The wrapped vault key is covered too, dropping it would take the profile out of the file mac */
+fn manifest(profile: &str, records: &[StoredRecord], wrap: Option<&String>, key: &ProfileKey) -> HmacSha256 {
+    let mut mac = <HmacSha256 as HmacKeyInit>::new_from_slice(&key.subkey("hmac")).expect("[!] Error: Creating hmac");
+    mac.update(profile.as_bytes());
+    mac.update(&[0u8]);
+    mac.update(&(records.len() as u64).to_le_bytes());
     for record in records {
-        // key = hash(nonce[..12] + key + nonce[12..])
-        new_key.push_str(&record.salt[..12]);
-        new_key.push_str(key);
-        new_key.push_str(&record.salt[12..]);
-        encrypted_records.push((*record).encrypt_record(&hash256(&new_key)));
-        new_key.clear();
+        mac.update(record.hmac().as_bytes());
     }
+    if let Some(wrap) = wrap {
+        mac.update(wrap.as_bytes());
+    }
+    mac
+}
 
-    let mut dump_file = DumpFile::load_dumpfile(path)?;
-
-    let profile = match profile {
-        Some(x) => x,
-        None => &dump_file.default.clone().unwrap()
-    };
-
-    dump_file.profiles.insert(profile.clone(), encrypted_records);
-    dump_file.dump_dumpfile(path)
+fn verify_manifest(profile: &str, records: &[StoredRecord], wrap: Option<&String>, key: &ProfileKey, mac: &str) -> bool {
+    match decode(mac) {
+        Ok(tag) => manifest(profile, records, wrap, key).verify_slice(&tag).is_ok(),
+        Err(_) => false,
+    }
 }
 
 pub fn generate_rand_password(size: usize) -> String {
@@ -327,35 +1908,17 @@ pub fn generate_rand_password(size: usize) -> String {
 
     let least_len = (size as f64 * 0.25) as usize;
 
-    password.extend(
-        upper_case
-            .choose_multiple(&mut rng(), least_len)
-            .cloned()
-    );
-
-    password.extend(
-        lower_case
-            .choose_multiple(&mut rng(), least_len)
-            .cloned()
-    );
-
-    password.extend(
-        numbers
-            .choose_multiple(&mut rng(), least_len)
-            .cloned()
-    );
-
-    password.extend(
-        special_chars
-            .choose_multiple(&mut rng(), least_len)
-            .cloned()
-    );
-
-    password.extend(
-        lower_case
-            .choose_multiple(&mut rng(), (size - (least_len * 4)) as usize)
-            .cloned()
-    );
+    /* drawn with replacement, sampling without it capped each class at its size (10 digits, 7 specials)
+     * and long passwords came out short */
+    let mut pick = |chars: &[char], count: usize| {
+        password.extend((0..count).filter_map(|_| chars.choose(&mut rng())));
+    };
+
+    pick(&upper_case, least_len);
+    pick(&lower_case, least_len);
+    pick(&numbers, least_len);
+    pick(&special_chars, least_len);
+    pick(&lower_case, size - (least_len * 4));
 
     password.shuffle(&mut rng());
 
@@ -367,14 +1930,28 @@ pub fn fgets() -> String {
     let _ = io::stdout().flush();
     io::stdin().read_line(&mut input).expect("[!] Error reading from stdin!");
 
-    return input.trim().to_owned();
+    input.trim().to_owned()
+}
+
+fn random_bytes(n: usize) -> Vec<u8> {
+    (0..n).map(|_| { random::<u8>() }).collect()
+}
+
+/* hex(nonce + ciphertext) with a random nonce */
+fn seal_message(key: &[u8], plaintext: &str) -> Result<String, String> {
+    let nonce = random_bytes(12);
+    let mut data = encode(&nonce);
+    data.push_str(&encrypt(key, &plaintext.to_string(), &nonce)?);
+    Ok(data)
 }
 
-fn hash256(text: &String) -> [u8; 32] {
-    let res = Sha256::digest(text.as_bytes());
-    let mut bytes = [0u8; 32];
-    bytes.copy_from_slice(&res);
-    bytes
+fn open_message(key: &[u8], data: &str) -> Result<String, String> {
+    if data.len() < 24 || !data.is_char_boundary(24) {
+        return Err("[!] Error: Message is truncated".to_string());
+    }
+
+    let nonce = decode(&data[..24]).map_err(|e| format!("[!] Error: {e}"))?;
+    decrypt(key, &data[24..].to_string(), &nonce)
 }
 
 fn encrypt(key: &[u8], plaintext: &String, nonce: &[u8]) -> Result<String, String> {
@@ -389,7 +1966,7 @@ fn encrypt(key: &[u8], plaintext: &String, nonce: &[u8]) -> Result<String, Strin
         },
     };
 
-    return Ok(encode(ciphertext));
+    Ok(encode(ciphertext))
 }
 
 fn decrypt(key: &[u8], ciphertext: &String, nonce: &[u8]) -> Result<String, String> {
@@ -410,5 +1987,246 @@ fn decrypt(key: &[u8], ciphertext: &String, nonce: &[u8]) -> Result<String, Stri
         },
     };
 
-    return Ok(String::from_utf8_lossy(&plaintext).to_string());
+    Ok(String::from_utf8_lossy(&plaintext).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::{env, process, sync::atomic::{AtomicUsize, Ordering}};
+
+    const MAIN: &str = "correct horse";
+    const WORK: &str = "battery staple";
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    fn records(entries: &[&str]) -> Vec<Record> {
+        entries.iter().map(|x| Record::new(&[x.to_string(), "alice".to_string(), "hunter2".to_string(), String::new(), String::new()])).collect()
+    }
+
+    /* The cheapest argon2id a vault may ask for, the tests aren't about the kdf */
+    fn profile() -> Profile {
+        Profile { kdf: Some(KdfParams::new(crate::kdf::MIN_MEMORY, 1, 1)), ..Default::default() }
+    }
+
+    /* A "main" and a "work" profile as they would be read back from disk */
+    fn vault() -> Value {
+        let mut file = DumpFile::new();
+        for (name, password, entries) in [("main", MAIN, ["github", "gitlab"]), ("work", WORK, ["aws", "jira"])] {
+            file.profiles.insert(name.to_string(), profile());
+            store(&mut file, &records(&entries), password, name).unwrap();
+        }
+        file.update_mac().unwrap();
+        serde_json::to_value(&file).unwrap()
+    }
+
+    /* Unlocks `profile` from a dump.json of its own in the temp directory */
+    fn open(value: &Value, profile: &str, password: &str) -> Result<Vec<String>, VaultError> {
+        let path = env::temp_dir().join(format!("rsafe-vault-{}-{}.json", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let path = path.to_str().unwrap();
+        fs::write(path, serde_json::to_string(value).unwrap()).unwrap();
+
+        let loaded = load(path, password, Some(&profile.to_string()));
+        let _ = fs::remove_file(path);
+        Ok(loaded?.unwrap_or_default().iter().map(|x| x.entry()).collect())
+    }
+
+    fn record_of<'a>(value: &'a mut Value, profile: &str, idx: usize) -> &'a mut Value {
+        &mut value["profiles"][profile]["records"][idx]
+    }
+
+    #[test]
+    fn intact_vault_unlocks() {
+        let value = vault();
+
+        assert_eq!(open(&value, "main", MAIN).unwrap(), ["github", "gitlab"]);
+        assert_eq!(open(&value, "work", WORK).unwrap(), ["aws", "jira"]);
+        assert!(matches!(open(&value, "main", WORK), Err(VaultError::WrongPassword)));
+    }
+
+    #[test]
+    fn modified_records_are_reported() {
+        let mut value = vault();
+        let data = record_of(&mut value, "main", 1)["data"].as_str().unwrap().to_string();
+        let flipped = if data.ends_with('0') { "1" } else { "0" };
+        record_of(&mut value, "main", 1)["data"] = Value::from(format!("{}{}", &data[..data.len() - 1], flipped));
+
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::Integrity { record: Some(2), .. })));
+    }
+
+    #[test]
+    fn malformed_salts_are_reported_not_sliced() {
+        for salt in ["ab", "", "zzzzzzzzzzzzzzzzzzzzzzzz", "00112233445566778899aabbccddeeff"] {
+            let mut value = vault();
+            record_of(&mut value, "main", 0)["salt"] = Value::from(salt);
+
+            assert!(matches!(open(&value, "main", MAIN), Err(VaultError::Integrity { record: Some(1), .. })), "salt {:?}", salt);
+        }
+    }
+
+    #[test]
+    fn reordered_or_removed_records_are_reported() {
+        let mut value = vault();
+        value["profiles"]["main"]["records"].as_array_mut().unwrap().swap(0, 1);
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::Integrity { record: None, .. })));
+
+        let mut value = vault();
+        value["profiles"]["main"]["records"].as_array_mut().unwrap().remove(1);
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::Integrity { record: None, .. })));
+    }
+
+    #[test]
+    fn removed_profiles_are_reported() {
+        let mut value = vault();
+        value["profiles"].as_object_mut().unwrap().remove("work");
+
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::FileIntegrity)));
+    }
+
+    #[test]
+    fn rolled_back_profiles_are_reported() {
+        let old = vault();
+
+        let mut file: DumpFile = serde_json::from_value(old.clone()).unwrap();
+        let mut work = records(&["aws", "jira"]);
+        work.extend(records(&["vpn"]));
+        file.verify_mac("work", &file.master_key("work", WORK).unwrap()).unwrap();
+        store(&mut file, &work, WORK, "work").unwrap();
+        file.update_mac().unwrap();
+
+        let mut value = serde_json::to_value(&file).unwrap();
+        assert_eq!(open(&value, "main", MAIN).unwrap(), ["github", "gitlab"]);
+        assert_eq!(open(&value, "work", WORK).unwrap(), ["aws", "jira", "vpn"]);
+
+        value["profiles"]["work"] = old["profiles"]["work"].clone();
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::FileIntegrity)));
+        assert!(matches!(open(&value, "work", WORK), Err(VaultError::FileIntegrity)));
+    }
+
+    #[test]
+    fn stripped_macs_are_reported() {
+        let mut value = vault();
+        value.as_object_mut().unwrap().remove("mac");
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::FileIntegrity)));
+
+        // without its wrapped vault key the profile would fall out of the file mac, its manifest covers it
+        let mut value = vault();
+        value["profiles"]["main"].as_object_mut().unwrap().remove("wrap");
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::Integrity { record: None, .. })));
+    }
+
+    #[test]
+    fn profiles_of_older_files_are_covered_together() {
+        /* both profiles exist before either is stored, like a vault from before the file mac */
+        let mut file = DumpFile::new();
+        file.profiles.insert("main".to_string(), profile());
+        file.profiles.insert("work".to_string(), profile());
+        store(&mut file, &records(&["github"]), MAIN, "main").unwrap();
+        store(&mut file, &records(&["aws"]), WORK, "work").unwrap();
+
+        assert_eq!(file.uncovered_profiles(), ["main", "work"]);
+        assert!(file.vault_key.get().is_none());
+
+        let dir = std::env::temp_dir().join(format!("rsafe-cover-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.json").to_string_lossy().to_string();
+        file.dump_dumpfile(&path).unwrap();
+        assert!(DumpFile::load_raw(&path).unwrap().get("mac").is_none());
+
+        let unlocked = [
+            ("main".to_string(), MAIN.to_string(), records(&["github"])),
+            ("work".to_string(), WORK.to_string(), records(&["aws"])),
+        ];
+        assert!(cover_profiles(&path, &unlocked[..1]).is_err());
+        cover_profiles(&path, &unlocked).unwrap();
+
+        let mut value = DumpFile::load_raw(&path).unwrap();
+        let file: DumpFile = serde_json::from_value(value.clone()).unwrap();
+        assert!(file.mac.is_some());
+        assert!(file.uncovered_profiles().is_empty());
+        assert_eq!(open(&value, "work", WORK).unwrap(), ["aws"]);
+
+        value["profiles"].as_object_mut().unwrap().remove("work");
+        assert!(matches!(open(&value, "main", MAIN), Err(VaultError::FileIntegrity)));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn debug_output_hides_the_vault_key() {
+        let mut file = DumpFile::new();
+        file.profiles.insert("main".to_string(), profile());
+        store(&mut file, &records(&["github"]), MAIN, "main").unwrap();
+
+        let key = file.vault_key.get().unwrap();
+        let printed = format!("{:?}", file);
+        assert!(!printed.contains(key.as_str()));
+        assert!(printed.contains("<redacted>"));
+    }
+
+    #[test]
+    fn legacy_profiles_move_to_argon2id_and_hkdf_when_stored() {
+        let mut file = DumpFile::new();
+        file.profiles.insert("main".to_string(), Profile::default());
+        store(&mut file, &records(&["github"]), MAIN, "main").unwrap();
+
+        let stored = &file.profiles["main"];
+        assert!(stored.kdf.is_some());
+        assert_eq!(stored.subkeys, Subkeys::Hkdf);
+
+        file.update_mac().unwrap();
+        let value = serde_json::to_value(&file).unwrap();
+        assert_eq!(value["profiles"]["main"]["subkeys"], "hkdf");
+        assert_eq!(open(&value, "main", MAIN).unwrap(), ["github"]);
+        assert!(matches!(open(&value, "main", WORK), Err(VaultError::WrongPassword)));
+    }
+
+    #[test]
+    fn old_passwords_are_kept_and_restored() {
+        let data = |password: &str| ["github".to_string(), "alice".to_string(), password.to_string()];
+        let mut record = Record::new(&data("first"));
+
+        // an edit that keeps the password doesn't add to the history
+        record = record.updated(&data("first"));
+        assert!(record.history().is_empty());
+
+        record = record.updated(&data("second")).updated(&data("third"));
+        let history: Vec<String> = record.history().iter().map(|x| x.password.clone()).collect();
+        assert_eq!(history, ["second", "first"]);
+
+        record.restore_password(2).unwrap();
+        assert_eq!(record.password(), "first");
+        let history: Vec<String> = record.history().iter().map(|x| x.password.clone()).collect();
+        assert_eq!(history, ["third", "second"]);
+
+        assert!(record.restore_password(0).is_err());
+        assert!(record.restore_password(3).is_err());
+        assert_eq!(record.password(), "first");
+    }
+
+    #[test]
+    fn history_is_capped_at_history_size() {
+        let mut record = Record::new(&["github".to_string(), "alice".to_string(), "0".to_string()]);
+        for n in 1..=config::DEFAULT_HISTORY_SIZE + 5 {
+            record = record.updated(&["github".to_string(), "alice".to_string(), n.to_string()]);
+        }
+
+        assert_eq!(record.history().len(), config::get().history_size);
+        assert_eq!(record.history()[0].password, (config::DEFAULT_HISTORY_SIZE + 4).to_string());
+    }
+
+    #[test]
+    fn generated_passwords_have_the_requested_length() {
+        for size in [1, 4, 16, 32, 64, 500] {
+            let password = generate_rand_password(size);
+            assert_eq!(password.chars().count(), size);
+        }
+
+        let password = generate_rand_password(64);
+        assert!(password.chars().any(|x| x.is_ascii_uppercase()));
+        assert!(password.chars().filter(|x| x.is_ascii_digit()).count() >= 16);
+        assert!(password.chars().filter(|x| "!@#$%&*".contains(*x)).count() >= 16);
+    }
 }